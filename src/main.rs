@@ -75,6 +75,7 @@
 //! - ✅ JSON-based server configuration
 //! - ✅ Support for any MCP server
 //! - ✅ Interactive shell mode
+//! - ✅ Terminal UI for browsing and calling tools
 //! - ✅ Server-specific arguments via --server-args
 //! - ✅ Daemon mode with persistent state
 //! - ✅ Project-aware: displays current working directory for all operations
@@ -83,27 +84,153 @@
 //!
 //! - **Protocol**: MCP 2025-06-18 (JSON-RPC 2.0)
 //! - **Transport**: STDIO / Unix socket (daemon)
-//! - **Dependencies**: serde, serde_json, anyhow, clap, nix
+//! - **Dependencies**: serde, serde_json, anyhow, clap, nix, notify, crossterm
 
 #[cfg(not(unix))]
 compile_error!("mcp-valve requires a Unix platform (Linux, macOS, BSD)");
 
 use anyhow::{anyhow, Context, Result};
-use clap::{Parser, Subcommand};
-use nix::sys::signal::{kill, Signal};
+use clap::{Parser, Subcommand, ValueEnum};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::{cursor, execute, queue, style, terminal};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::sys::socket::{connect, getsockopt, socket, sockopt, AddressFamily, SockFlag, SockType, UnixAddr};
+use nix::sys::signal::{kill, raise, signal, SigHandler, Signal};
 use nix::sys::stat::{umask, Mode};
 use nix::unistd::{setsid, Pid};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::c_int;
 use std::fs;
-use std::io::{BufRead, BufReader, Read, Write};
+use std::io::{BufRead, BufReader, IsTerminal, Read, Write};
+use std::os::fd::{AsFd, AsRawFd};
 use std::os::unix::fs::PermissionsExt;
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::os::unix::process::CommandExt;
-use std::path::PathBuf;
-use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex, Once, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// ============================================================================
+// Exit Codes
+// ============================================================================
+
+/// Failure categories with stable process exit codes
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Failure {
+    /// Config file missing, unreadable or invalid
+    Config,
+    /// `--server` does not name a configured server or alias
+    ServerNotFound,
+    /// The server could not be spawned, or its daemon is not running
+    Unavailable,
+    /// Invalid or error JSON-RPC response, or the server went away
+    Protocol,
+    /// The tool ran and reported `isError`
+    Tool,
+    /// No response before the socket timeout
+    Timeout,
+}
+
+impl Failure {
+    const ALL: [Failure; 6] = [
+        Failure::Config,
+        Failure::ServerNotFound,
+        Failure::Unavailable,
+        Failure::Protocol,
+        Failure::Tool,
+        Failure::Timeout,
+    ];
+
+    fn exit_code(self) -> i32 {
+        match self {
+            Failure::Tool => 3,
+            Failure::ServerNotFound => 68,
+            Failure::Unavailable => 69,
+            Failure::Timeout => 75,
+            Failure::Protocol => 76,
+            Failure::Config => 78,
+        }
+    }
+
+    /// Name used in daemon responses and `--error-format json`
+    fn name(self) -> &'static str {
+        match self {
+            Failure::Config => "config",
+            Failure::ServerNotFound => "server_not_found",
+            Failure::Unavailable => "unavailable",
+            Failure::Protocol => "protocol",
+            Failure::Tool => "tool",
+            Failure::Timeout => "timeout",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|f| f.name() == name)
+    }
+
+    /// Tags `error` with this category without changing how it prints
+    fn wrap(self, error: anyhow::Error) -> anyhow::Error {
+        anyhow::Error::new(Classified { failure: self, error })
+    }
+
+    /// The category of an error: an explicit tag, else inferred from its causes
+    fn of(error: &anyhow::Error) -> Option<Self> {
+        let tagged = error.chain().find_map(|e| e.downcast_ref::<Classified>());
+        if let Some(classified) = tagged {
+            return Some(classified.failure);
+        }
+        if rpc_error(error).is_some() {
+            return Some(Failure::Protocol);
+        }
+        error.chain().find_map(|e| {
+            let io = e.downcast_ref::<std::io::Error>()?;
+            matches!(io.kind(), std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock)
+                .then_some(Failure::Timeout)
+        })
+    }
+}
+
+/// The JSON-RPC error behind `error`, looking through `Failure` tags
+fn rpc_error(error: &anyhow::Error) -> Option<&RpcError> {
+    error.chain().find_map(|e| {
+        e.downcast_ref::<RpcError>()
+            .or_else(|| e.downcast_ref::<Classified>()?.error.downcast_ref::<RpcError>())
+    })
+}
+
+/// An error tagged with a `Failure`; displays exactly like the wrapped error
+struct Classified {
+    failure: Failure,
+    error: anyhow::Error,
+}
+
+impl std::fmt::Display for Classified {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Only the top message; causes are reached through `source`
+        write!(f, "{}", self.error)
+    }
+}
+
+impl std::fmt::Debug for Classified {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&self.error, f)
+    }
+}
+
+impl std::error::Error for Classified {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
 
 // ============================================================================
 // Configuration
@@ -111,6 +238,8 @@ use std::time::Duration;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 struct ServerProfile {
+    /// Server command; with `container`, the command run inside the image (may be empty)
+    #[serde(default)]
     command: Vec<String>,
     #[serde(default)]
     default_args: Vec<String>,
@@ -120,12 +249,254 @@ struct ServerProfile {
     description: String,
     #[serde(default)]
     env: HashMap<String, String>,
+    /// Start the server with an empty environment instead of inheriting ours
+    #[serde(default)]
+    clear_env: bool,
+    /// Host variables copied into the server environment when present
+    #[serde(default)]
+    env_passthrough: Vec<String>,
+    /// Command run before the server starts; a nonzero exit aborts the launch
+    #[serde(default)]
+    pre_start: Vec<String>,
+    /// Command run after the daemon stops
+    #[serde(default)]
+    post_stop: Vec<String>,
+    /// Tool calls run when a daemon starts, before it accepts clients
+    #[serde(default)]
+    warmup: Vec<ToolCallSpec>,
+    /// Abort daemon startup if a warmup call fails (otherwise failures are only logged)
+    #[serde(default)]
+    warmup_required: bool,
+    /// Tool call that must succeed before a daemon reports ready
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    health_check: Option<HealthCheck>,
+    /// Deep-merged into the `initialize` request params (e.g. client capabilities)
+    #[serde(default)]
+    initialize_params: serde_json::Map<String, Value>,
+    /// Server capabilities that must be declared in `initialize`, e.g. `tools` or `resources.subscribe`
+    #[serde(default)]
+    require_capabilities: Vec<String>,
+    /// Stop the daemon after this many seconds without a client request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    idle_timeout: Option<u64>,
+    /// After initialize, retry `tools/list` for up to this many seconds until it succeeds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ready_probe: Option<u64>,
+    /// Kill the server when it doesn't answer a request within this many seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request_timeout: Option<u64>,
+    /// Daemon: abandon timed-out requests and restart the server after this many in a row
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    restart_after_timeouts: Option<u32>,
+    /// How long `start-daemon` waits for the daemon to become ready
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    daemon_start_timeout_secs: Option<u64>,
+    /// Working directory for the server (template vars expanded, relative to the current directory)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cwd: Option<String>,
+    /// `call` starts the daemon when it isn't running instead of failing
+    #[serde(default)]
+    auto_daemon: bool,
+    /// `tui` and `call --args-stdin-jsonl` fail without a running daemon instead of
+    /// starting the server directly (same as `--no-fallback`)
+    #[serde(default)]
+    no_fallback: bool,
+    /// Tools a daemon serves; others are hidden from `tools/list` and refused (empty = all)
+    #[serde(default)]
+    allowed_tools: Vec<String>,
+    /// Refuse tools matching `deny_tools` (or the default mutating patterns)
+    #[serde(default)]
+    read_only: bool,
+    /// Case-insensitive `*` patterns refused in read-only mode
+    #[serde(default)]
+    deny_tools: Vec<String>,
+    /// Tools `call --retry-on-tool-error` may call again after a tool error
+    #[serde(default)]
+    idempotent_tools: Vec<String>,
+    /// Arguments merged under every call's own, per tool name or `*` for all tools
+    #[serde(default)]
+    tool_defaults: HashMap<String, serde_json::Map<String, Value>>,
+    /// Run the server in a container instead of on the host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    container: Option<ContainerSpec>,
 }
 
-#[derive(Debug, Deserialize)]
+/// A container image to run the server from, with stdio attached
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+struct ContainerSpec {
+    image: String,
+    /// Container CLI, e.g. `docker` or `podman`
+    #[serde(default = "default_container_runtime")]
+    runtime: String,
+    /// `-v` mounts (template variables expanded)
+    #[serde(default)]
+    volumes: Vec<String>,
+    /// Extra `run` arguments placed before the image
+    #[serde(default)]
+    run_args: Vec<String>,
+}
+
+fn default_container_runtime() -> String {
+    "docker".to_string()
+}
+
+/// A tool call defined in the config
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct ToolCallSpec {
+    tool: String,
+    #[serde(default = "empty_object")]
+    args: Value,
+}
+
+/// A startup probe: the call must succeed and, with `expect`, match its result
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct HealthCheck {
+    #[serde(flatten)]
+    call: ToolCallSpec,
+    /// A string the result text must contain, or JSON the result value must contain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expect: Option<Value>,
+}
+
+fn empty_object() -> Value {
+    json!({})
+}
+
+#[derive(Debug, Default, Deserialize)]
 struct ServerConfig {
+    /// JSON Schema reference for editors (see `config schema`); ignored here
+    #[serde(rename = "$schema", default)]
+    _schema: Option<String>,
+    /// Other config files to merge, relative to this file (`*` wildcards allowed in the file name)
+    #[serde(default)]
+    include: Vec<String>,
+    /// Short names for servers, e.g. `"pw": "playwright"`
+    #[serde(default)]
+    aliases: HashMap<String, String>,
     #[serde(flatten)]
     servers: HashMap<String, ServerProfile>,
+    /// The file this config was loaded from (the highest-precedence file when layered)
+    #[serde(skip)]
+    path: PathBuf,
+    /// The file each profile was loaded from
+    #[serde(skip)]
+    sources: HashMap<String, PathBuf>,
+    /// Profiles replaced by a same-named one from another file: (name, replaced file)
+    #[serde(skip)]
+    shadowed: Vec<(String, PathBuf)>,
+    /// How the files were chosen, so a daemon started from this config reads the same ones
+    #[serde(skip)]
+    selection: ConfigSelection,
+}
+
+/// The config files a command reads. A daemon gets its starter's selection rather
+/// than a single profile's file, so layers and includes survive reloads and restarts
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ConfigSelection {
+    /// One file (with its includes): `--config`, `MCP_VALVE_CONFIG` or a default location
+    File(PathBuf),
+    /// `MCP_VALVE_CONFIG_PATH` entries, lowest precedence first
+    Layers(Vec<PathBuf>),
+}
+
+impl Default for ConfigSelection {
+    fn default() -> Self {
+        ConfigSelection::File(PathBuf::new())
+    }
+}
+
+impl ConfigSelection {
+    /// `--config` or `MCP_VALVE_CONFIG` wins; otherwise `MCP_VALVE_CONFIG_PATH` layers
+    /// several files; otherwise the default locations are searched
+    fn resolve(cli_config: Option<PathBuf>) -> Result<Self> {
+        if cli_config.is_none() && std::env::var_os("MCP_VALVE_CONFIG").is_none() {
+            if let Ok(layers) = std::env::var("MCP_VALVE_CONFIG_PATH") {
+                let layers = layers.split(':').filter(|e| !e.is_empty()).map(PathBuf::from).collect();
+                return Ok(ConfigSelection::Layers(layers));
+            }
+        }
+        get_config_path(cli_config).map(ConfigSelection::File)
+    }
+
+    /// The same selection with absolute paths, for a daemon whose working dir may differ
+    fn absolute(&self) -> Self {
+        let absolute = |path: &PathBuf| std::path::absolute(path).unwrap_or_else(|_| path.clone());
+        match self {
+            ConfigSelection::File(path) => ConfigSelection::File(absolute(path)),
+            ConfigSelection::Layers(layers) => ConfigSelection::Layers(layers.iter().map(absolute).collect()),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Layers `other` on top of this config: profiles with the same name are replaced
+    fn merge(&mut self, other: ServerConfig) {
+        for name in other.servers.keys() {
+            if self.servers.contains_key(name) {
+                self.shadowed.push((name.clone(), self.source(name).to_path_buf()));
+            }
+        }
+        self.shadowed.extend(other.shadowed);
+        self.servers.extend(other.servers);
+        self.aliases.extend(other.aliases);
+        self.sources.extend(other.sources);
+        self.path = other.path;
+    }
+
+    /// The file a profile was loaded from
+    fn source(&self, name: &str) -> &Path {
+        self.sources.get(name).unwrap_or(&self.path)
+    }
+
+    /// Resolves a `--server` value to its config key and profile.
+    ///
+    /// Exact matches always win, then aliases. Otherwise a unique case-insensitive
+    /// prefix match (e.g. `play` -> `playwright`), then a unique substring match, is accepted.
+    fn resolve(&self, name: &str) -> Result<(String, &ServerProfile)> {
+        if let Some((key, profile)) = self.servers.get_key_value(name) {
+            return Ok((key.clone(), profile));
+        }
+
+        if let Some(target) = self.aliases.get(name) {
+            return match self.servers.get_key_value(target) {
+                Some((key, profile)) => Ok((key.clone(), profile)),
+                None => Err(Failure::ServerNotFound.wrap(anyhow!(
+                    "Alias '{}' points to server '{}', which is not in the config",
+                    name,
+                    target
+                ))),
+            };
+        }
+
+        let needle = name.to_lowercase();
+        let matching = |pred: &dyn Fn(&str) -> bool| {
+            let mut keys: Vec<&String> = self.servers.keys().filter(|k| pred(&k.to_lowercase())).collect();
+            keys.sort();
+            keys
+        };
+
+        let mut candidates = matching(&|k| k == needle);
+        if candidates.is_empty() {
+            candidates = matching(&|k| k.starts_with(&needle));
+        }
+        if candidates.is_empty() {
+            candidates = matching(&|k| k.contains(&needle));
+        }
+
+        match candidates.as_slice() {
+            [] => Err(Failure::ServerNotFound.wrap(anyhow!("Server '{}' not found in config", name))),
+            [key] => {
+                eprintln!("Using server '{}' (matched '{}')", key, name);
+                Ok(((*key).clone(), &self.servers[*key]))
+            }
+            keys => Err(Failure::ServerNotFound.wrap(anyhow!(
+                "Server '{}' is ambiguous. Candidates: {}",
+                name,
+                keys.iter().map(|k| k.as_str()).collect::<Vec<_>>().join(", ")
+            ))),
+        }
+    }
 }
 
 /// Resolves config file path with priority:
@@ -145,16 +516,24 @@ fn get_config_path(cli_config: Option<PathBuf>) -> Result<PathBuf> {
         return Ok(PathBuf::from(path));
     }
 
-    let home = std::env::var("HOME").context("HOME environment variable not set")?;
-
     // 3. XDG_CONFIG_HOME if set
-    if let Ok(xdg) = std::env::var("XDG_CONFIG_HOME") {
-        let path = PathBuf::from(xdg).join("mcp-valve/servers.json");
-        if path.exists() {
-            return Ok(path);
-        }
+    let xdg_path = std::env::var("XDG_CONFIG_HOME")
+        .ok()
+        .map(|xdg| PathBuf::from(xdg).join("mcp-valve/servers.json"));
+    if let Some(path) = xdg_path.as_ref().filter(|p| p.exists()) {
+        return Ok(path.clone());
     }
 
+    // HOME is only needed for the remaining defaults (minimal containers/CI may lack it)
+    let Ok(home) = std::env::var("HOME") else {
+        return xdg_path.ok_or_else(|| {
+            anyhow!(
+                "Cannot locate config: HOME is not set.\n\
+                Use --config, MCP_VALVE_CONFIG, or XDG_CONFIG_HOME"
+            )
+        });
+    };
+
     // 4. XDG default location
     let xdg_default = PathBuf::from(&home).join(".config/mcp-valve/servers.json");
     if xdg_default.exists() {
@@ -165,804 +544,6492 @@ fn get_config_path(cli_config: Option<PathBuf>) -> Result<PathBuf> {
     Ok(PathBuf::from(&home).join(".claude/scripts/mcp-servers.json"))
 }
 
+/// A server defined by `MCP_VALVE_SERVER_NAME` and `MCP_VALVE_SERVER_COMMAND`, with
+/// optional `MCP_VALVE_SERVER_ARGS` and `MCP_VALVE_SERVER_ENV`, for running without
+/// a config file (CI, containers). Daemon support is on, as for any server used with `call`
+fn env_server_profile() -> Result<Option<(String, ServerProfile)>> {
+    let var = |name: &str| std::env::var(name).ok().filter(|value| !value.trim().is_empty());
+    let (name, command) = match (var("MCP_VALVE_SERVER_NAME"), var("MCP_VALVE_SERVER_COMMAND")) {
+        (Some(name), Some(command)) => (name, command),
+        (None, None) => return Ok(None),
+        (Some(_), None) => return Err(anyhow!("MCP_VALVE_SERVER_NAME is set but MCP_VALVE_SERVER_COMMAND is not")),
+        (None, Some(_)) => return Err(anyhow!("MCP_VALVE_SERVER_COMMAND is set but MCP_VALVE_SERVER_NAME is not")),
+    };
+
+    let command = parse_command_words(&command, "MCP_VALVE_SERVER_COMMAND")?;
+    let default_args = match var("MCP_VALVE_SERVER_ARGS") {
+        Some(args) => parse_command_words(&args, "MCP_VALVE_SERVER_ARGS")?,
+        None => Vec::new(),
+    };
+    let env: HashMap<String, String> = match var("MCP_VALVE_SERVER_ENV") {
+        Some(env) => serde_json::from_str(&env)
+            .context("MCP_VALVE_SERVER_ENV must be a JSON object of strings")?,
+        None => HashMap::new(),
+    };
+
+    let profile = serde_json::from_value(json!({
+        "command": command,
+        "default_args": default_args,
+        "env": env,
+        "supports_daemon": true,
+        "description": "Defined by MCP_VALVE_SERVER_* environment variables",
+    }))?;
+    Ok(Some((name, profile)))
+}
+
+/// A JSON array of strings, or a shell-style string split on whitespace with
+/// 'single' and "double" quotes and backslash escapes
+fn parse_command_words(value: &str, var: &str) -> Result<Vec<String>> {
+    if value.trim_start().starts_with('[') {
+        return serde_json::from_str(value)
+            .with_context(|| format!("{} must be a JSON array of strings", var));
+    }
+
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('\''), c) => word.get_or_insert_with(String::new).push(c),
+            (_, '\\') => {
+                let escaped = chars.next().ok_or_else(|| anyhow!("{} ends with a backslash", var))?;
+                word.get_or_insert_with(String::new).push(escaped);
+            }
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (_, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err(anyhow!("Unterminated quote in {}", var));
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Asks the user to choose a server when `--server` is omitted.
+///
+/// Only prompts when stdin is a TTY; otherwise keeps the hard error so scripts fail loudly.
+fn pick_server(config: &ServerConfig) -> Result<String> {
+    let missing = || anyhow!("--server required. Use 'list-servers' to see available servers.");
+
+    if !std::io::stdin().is_terminal() || config.servers.is_empty() {
+        return Err(missing());
+    }
+
+    let mut names: Vec<&String> = config.servers.keys().collect();
+    names.sort();
+
+    eprintln!("Select a server:");
+    for (i, name) in names.iter().enumerate() {
+        let desc = &config.servers[*name].description;
+        if desc.is_empty() {
+            eprintln!("  {}) {}", i + 1, name);
+        } else {
+            eprintln!("  {}) {} - {}", i + 1, name, desc);
+        }
+    }
+    eprint!("Server [1-{}]: ", names.len());
+    std::io::stderr().flush()?;
+
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    let input = input.trim();
+
+    match input.parse::<usize>() {
+        Ok(n) if (1..=names.len()).contains(&n) => Ok(names[n - 1].clone()),
+        Ok(_) => Err(anyhow!("Invalid selection: {}", input)),
+        Err(_) if input.is_empty() => Err(missing()),
+        // Allow typing a name (resolved like --server)
+        Err(_) => Ok(input.to_string()),
+    }
+}
+
+/// Resolves a server name for commands that don't need the profile itself
+/// (stop/status), falling back to the name as given if the config can't resolve it
+fn resolve_server_name(cli_config: Option<PathBuf>, name: String) -> String {
+    load_server_config(cli_config)
+        .ok()
+        .and_then(|config| config.resolve(&name).ok().map(|(key, _)| key))
+        .unwrap_or(name)
+}
+
+/// Set by `--strict-config`: a server name defined in more than one file is an error
+static STRICT_CONFIG: AtomicBool = AtomicBool::new(false);
+
+/// Set once the shadowed-profile warning was printed, so repeated loads stay quiet
+static SHADOWED_WARNED: AtomicBool = AtomicBool::new(false);
+
 fn load_server_config(cli_config: Option<PathBuf>) -> Result<ServerConfig> {
-    let config_path = get_config_path(cli_config)?;
+    let selection = ConfigSelection::resolve(cli_config).map_err(|e| Failure::Config.wrap(e))?;
+    load_selected_config(&selection)
+}
+
+fn load_selected_config(selection: &ConfigSelection) -> Result<ServerConfig> {
+    let config = read_server_config(selection).map_err(|e| Failure::Config.wrap(e))?;
+    check_shadowed(&config)?;
+    Ok(config)
+}
+
+/// Reports server names defined in several files (includes or layers), naming the
+/// file that was overridden and the one in use: an error with `--strict-config`,
+/// otherwise a warning
+fn check_shadowed(config: &ServerConfig) -> Result<()> {
+    if config.shadowed.is_empty() {
+        return Ok(());
+    }
+    let lines: Vec<String> = config.shadowed
+        .iter()
+        .map(|(name, replaced)| format!(
+            "'{}' in {} is overridden by {}",
+            name,
+            replaced.display(),
+            config.source(name).display()
+        ))
+        .collect();
+
+    if STRICT_CONFIG.load(Ordering::SeqCst) {
+        return Err(Failure::Config.wrap(anyhow!(
+            "Server defined in more than one config file (--strict-config):\n  {}",
+            lines.join("\n  ")
+        )));
+    }
+    if !SHADOWED_WARNED.swap(true, Ordering::SeqCst) {
+        for line in lines {
+            eprintln!("{} server {}", stderr_label("Warning:", COLOR_WARNING), line);
+        }
+    }
+    Ok(())
+}
+
+fn read_server_config(selection: &ConfigSelection) -> Result<ServerConfig> {
+    let env_server = env_server_profile()?;
+    let mut config = read_config_files(selection, env_server.is_some())?;
+    config.selection = selection.clone();
+    // File profiles and aliases keep their names; the env server only fills a gap
+    if let Some((name, profile)) = env_server {
+        if !config.servers.contains_key(&name) && !config.aliases.contains_key(&name) {
+            config.sources.insert(name.clone(), config.path.clone());
+            config.servers.insert(name, profile);
+        }
+    }
+    Ok(config)
+}
+
+/// Loads the config file(s). With `allow_missing` (a server is defined in the
+/// environment), a missing file is an empty config instead of an error
+fn read_config_files(selection: &ConfigSelection, allow_missing: bool) -> Result<ServerConfig> {
+    let config_path = match selection {
+        ConfigSelection::Layers(layers) => return load_layered_config(layers),
+        ConfigSelection::File(path) => path.clone(),
+    };
 
+    if !config_path.exists() && allow_missing {
+        return Ok(ServerConfig { path: config_path, ..Default::default() });
+    }
     if !config_path.exists() {
-        let home = std::env::var("HOME").unwrap_or_default();
+        let suggested = std::env::var("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|_| std::env::var("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .map(|dir| dir.join("mcp-valve/servers.json"))
+            .unwrap_or_else(|_| PathBuf::from("~/.config/mcp-valve/servers.json"));
         return Err(anyhow!(
-            "Configuration file not found.\n\n\
+            "Configuration file not found: {}\n\n\
             Searched locations (in order):\n  \
             1. --config flag or MCP_VALVE_CONFIG env var\n  \
             2. $XDG_CONFIG_HOME/mcp-valve/servers.json\n  \
             3. ~/.config/mcp-valve/servers.json\n  \
             4. ~/.claude/scripts/mcp-servers.json\n\n\
-            Create a config file at: {}\n\n\
+            Create a config file at: {} (or run 'mcp-valve config edit')\n\n\
             Example:\n\
-            {{\n  \
-              \"server-name\": {{\n    \
-                \"command\": [\"npx\", \"@example/mcp-server\"],\n    \
-                \"default_args\": [],\n    \
-                \"supports_daemon\": true,\n    \
-                \"description\": \"Example MCP server\",\n    \
-                \"env\": {{}}\n  \
-              }}\n\
-            }}",
-            PathBuf::from(&home).join(".config/mcp-valve/servers.json").display()
+            {}",
+            config_path.display(),
+            suggested.display(),
+            CONFIG_EXAMPLE.trim_end()
         ));
     }
 
-    let config_content = fs::read_to_string(&config_path)
-        .with_context(|| format!("Failed to read config: {}", config_path.display()))?;
+    load_config_file(&config_path)
+}
 
-    let config: ServerConfig = serde_json::from_str(&config_content)
-        .with_context(|| format!("Invalid JSON in config: {}", config_path.display()))?;
+/// Starter config, shown when none is found and written by `config edit`
+const CONFIG_EXAMPLE: &str = r#"{
+  "server-name": {
+    "command": ["npx", "@example/mcp-server"],
+    "default_args": [],
+    "supports_daemon": true,
+    "description": "Example MCP server",
+    "env": {}
+  }
+}
+"#;
 
-    Ok(config)
+fn load_config_file(config_path: &Path) -> Result<ServerConfig> {
+    load_config_file_with_includes(config_path, &mut Vec::new())
 }
 
-// ============================================================================
-// CLI Definition
-// ============================================================================
+/// Loads a config file and its `include`s. `chain` holds the files currently being
+/// loaded so include cycles can be reported.
+fn load_config_file_with_includes(config_path: &Path, chain: &mut Vec<PathBuf>) -> Result<ServerConfig> {
+    let canonical = fs::canonicalize(config_path).unwrap_or_else(|_| config_path.to_path_buf());
+    if chain.contains(&canonical) {
+        let cycle: Vec<String> = chain
+            .iter()
+            .chain([&canonical])
+            .map(|p| p.display().to_string())
+            .collect();
+        return Err(anyhow!("Config include cycle: {}", cycle.join(" -> ")));
+    }
 
-#[derive(Parser)]
-#[command(name = "mcp-valve")]
-#[command(about = "Unified MCP CLI - Generic MCP Protocol Client")]
-#[command(version = "1.1.0")]
-struct Cli {
-    /// Server name from config (e.g., playwright, zen)
-    #[arg(short, long)]
-    server: Option<String>,
+    let config_content = fs::read_to_string(config_path)
+        .with_context(|| format!("Failed to read config: {}", config_path.display()))?;
 
-    /// Additional server arguments (JSON array, e.g., '["--gui", "--browser", "firefox"]')
-    #[arg(long)]
-    server_args: Option<String>,
+    let mut config: ServerConfig = serde_json::from_str(&config_content)
+        .with_context(|| format!("Invalid JSON in config: {}", config_path.display()))?;
+    config.path = canonical.clone();
+    config.sources = config
+        .servers
+        .keys()
+        .map(|name| (name.clone(), config.path.clone()))
+        .collect();
 
-    /// Path to config file (overrides default locations)
-    #[arg(short, long, global = true)]
-    config: Option<PathBuf>,
+    if config.include.is_empty() {
+        return Ok(config);
+    }
 
-    #[command(subcommand)]
-    command: Commands,
+    // Included files are merged first so the including file wins on name conflicts
+    chain.push(canonical);
+    let base_dir = config.path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let mut merged = ServerConfig::default();
+    for pattern in &config.include {
+        for file in expand_include(&base_dir, pattern)? {
+            merged.merge(load_config_file_with_includes(&file, chain)?);
+        }
+    }
+    chain.pop();
+
+    merged.merge(config);
+    Ok(merged)
 }
 
-#[derive(Subcommand)]
-enum Commands {
-    /// List all configured servers
-    ListServers,
+/// Resolves an `include` entry relative to the including file's directory.
+/// A `*` wildcard in the file name matches any run of characters (sorted by name).
+fn expand_include(base_dir: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let path = base_dir.join(pattern);
+    let file_pattern = path.file_name().and_then(|f| f.to_str()).unwrap_or_default();
 
-    /// Call any MCP tool
-    Call {
-        /// Tool name (e.g., browser_navigate, chat)
-        tool: String,
-        /// Arguments as JSON string
-        #[arg(short, long, default_value = "{}")]
-        args: String,
-    },
+    if !file_pattern.contains('*') {
+        if !path.exists() {
+            return Err(anyhow!("Included config not found: {}", path.display()));
+        }
+        return Ok(vec![path]);
+    }
 
-    /// List all available tools from the server
-    ListTools,
+    let dir = path.parent().unwrap_or(base_dir);
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read include directory: {}", dir.display()))?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.is_file()
+                && p.file_name()
+                    .and_then(|f| f.to_str())
+                    .is_some_and(|name| wildcard_match(file_pattern, name))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
 
-    /// Interactive shell mode
-    Shell,
+/// Matches `text` against a pattern where `*` matches any run of characters
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((prefix, rest)) => match text.strip_prefix(prefix) {
+            Some(tail) => tail
+                .char_indices()
+                .map(|(i, _)| i)
+                .chain([tail.len()])
+                .any(|i| wildcard_match(rest, &tail[i..])),
+            None => false,
+        },
+    }
+}
 
-    /// Start background daemon (requires supports_daemon: true)
-    StartDaemon,
+/// Loads a colon-separated list of config files and/or directories (whose `*.json`
+/// files are loaded in name order), later entries overriding earlier profiles by name.
+/// Missing entries are skipped, like PATH.
+fn load_layered_config(layers: &[PathBuf]) -> Result<ServerConfig> {
+    let mut files = Vec::new();
+    for path in layers {
+        if path.is_dir() {
+            let mut jsons: Vec<PathBuf> = fs::read_dir(path)
+                .with_context(|| format!("Failed to read config directory: {}", path.display()))?
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+                .collect();
+            jsons.sort();
+            files.extend(jsons);
+        } else if path.exists() {
+            files.push(path.clone());
+        }
+    }
 
-    /// Stop background daemon
-    StopDaemon,
+    if files.is_empty() {
+        let layers: Vec<String> = layers.iter().map(|p| p.display().to_string()).collect();
+        return Err(anyhow!("No config files found in MCP_VALVE_CONFIG_PATH: {}", layers.join(":")));
+    }
 
-    /// Check daemon status
-    DaemonStatus,
+    let mut config = ServerConfig::default();
+    for file in files {
+        config.merge(load_config_file(&file)?);
+    }
+    Ok(config)
 }
 
 // ============================================================================
-// Template Variable Expansion
+// Config Import/Export
 // ============================================================================
 
-/// Sanitizes server name to prevent path traversal attacks
-///
-/// Only allows alphanumeric characters, hyphens, and underscores
-fn sanitize_server_name(name: &str) -> String {
-    name.chars()
-        .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
-        .collect()
+/// One line of `config export --ndjson`
+#[derive(Deserialize, Serialize)]
+struct ProfileRecord {
+    name: String,
+    profile: ServerProfile,
 }
 
-/// Expands template variables in argument strings
-///
-/// Supported variables:
-/// - {profile_dir}: .mcp-profile/<server-name> (sanitized)
-/// - {pid}: Process ID
-/// - {cwd}: Current working directory
-///
-/// Security: Server names are sanitized to prevent path traversal
-fn expand_template_vars(arg: &str, server_name: &str) -> String {
-    let safe_server_name = sanitize_server_name(server_name);
-    let profile_dir = PathBuf::from(".mcp-profile").join(&safe_server_name);
-    let profile_dir_str = profile_dir.to_str().unwrap_or("");
-    let pid = std::process::id().to_string();
-    let cwd = std::env::current_dir()
-        .ok()
-        .and_then(|p| p.to_str().map(|s| s.to_string()))
-        .unwrap_or_else(|| ".".to_string());
+/// Top-level config keys that are not server profiles
+const RESERVED_CONFIG_KEYS: [&str; 3] = ["include", "aliases", "$schema"];
 
-    arg.replace("{profile_dir}", profile_dir_str)
-        .replace("{pid}", &pid)
-        .replace("{cwd}", &cwd)
-}
+fn export_config(config: &ServerConfig, ndjson: bool) -> Result<()> {
+    let mut names: Vec<&String> = config.servers.keys().collect();
+    names.sort();
 
-// ============================================================================
-// MCP Client (Generic)
-// ============================================================================
+    if !ndjson {
+        let servers: serde_json::Map<String, Value> = names
+            .iter()
+            .map(|name| Ok(((*name).clone(), serde_json::to_value(&config.servers[*name])?)))
+            .collect::<Result<_>>()?;
+        println!("{}", serde_json::to_string_pretty(&servers)?);
+        return Ok(());
+    }
 
-struct McpClient {
-    child: Child,
-    stdin: ChildStdin,
-    stdout: BufReader<ChildStdout>,
-    request_id: u64,
+    for name in names {
+        let record = ProfileRecord {
+            name: name.clone(),
+            profile: config.servers[name].clone(),
+        };
+        // Through Value so map keys (e.g. env) come out sorted
+        println!("{}", serde_json::to_value(&record)?);
+    }
+    Ok(())
 }
 
-impl McpClient {
-    fn start(profile: &ServerProfile, extra_args: Option<Vec<String>>, server_name: &str) -> Result<Self> {
-        eprintln!("🚀 Starting MCP server...");
-
-        if profile.command.is_empty() {
-            return Err(anyhow!("Server profile has empty command"));
-        }
-
-        let mut cmd = Command::new(&profile.command[0]);
-
-        // Add command args (e.g., for npx: "@playwright/mcp@latest")
-        if profile.command.len() > 1 {
-            cmd.args(&profile.command[1..]);
-        }
-
-        // Add args: if --server-args was provided (even if empty), use it to override default_args
-        // Otherwise use default_args from profile
-        // Template variables are expanded for both default_args and extra_args
-        let args_to_use = match extra_args {
-            Some(args) => args.iter().map(|arg| expand_template_vars(arg, server_name)).collect(),
-            None => profile.default_args.iter().map(|arg| expand_template_vars(arg, server_name)).collect::<Vec<String>>(),
+/// The `{"mcpServers": ...}` config other MCP clients read. The first word of
+/// `command` becomes `command` and the rest, followed by `default_args`, `args`.
+/// Container profiles have no equivalent there and are left out
+fn to_mcp_servers(config: &ServerConfig) -> Value {
+    let mut servers = serde_json::Map::new();
+    let mut names: Vec<&String> = config.servers.keys().collect();
+    names.sort();
+    for name in names {
+        let profile = &config.servers[name];
+        let Some((command, args)) = profile.command.split_first().filter(|_| profile.container.is_none()) else {
+            eprintln!("{} skipping '{}': container profiles can't be exported", stderr_label("Warning:", COLOR_WARNING), name);
+            continue;
         };
-        cmd.args(&args_to_use);
-
-        // Set environment variables
-        for (key, value) in &profile.env {
-            cmd.env(key, value);
+        let mut server = json!({
+            "command": command,
+            "args": args.iter().chain(&profile.default_args).collect::<Vec<_>>(),
+        });
+        if !profile.env.is_empty() {
+            server["env"] = json!(profile.env);
         }
+        servers.insert(name.clone(), server);
+    }
+    json!({"mcpServers": servers})
+}
 
-        let mut child = cmd
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
-            .spawn()
-            .with_context(|| format!("Failed to spawn MCP server: {:?}", profile.command))?;
-
-        let stdin = child.stdin.take().unwrap();
-        let stdout = BufReader::new(child.stdout.take().unwrap());
+/// Reads `{"mcpServers": ...}` entries as profiles: `command` alone becomes the
+/// command, `args` the `default_args`, and daemon support is on. Entries without a
+/// `command` (URL-based servers) are skipped
+fn from_mcp_servers(content: &str) -> Result<Vec<(String, ServerProfile)>> {
+    #[derive(Deserialize)]
+    struct McpServer {
+        command: Option<String>,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default)]
+        env: HashMap<String, String>,
+    }
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct McpServersFile {
+        mcp_servers: HashMap<String, McpServer>,
+    }
 
-        let mut mcp = Self {
-            child,
-            stdin,
-            stdout,
-            request_id: 0,
+    let file: McpServersFile = serde_json::from_str(content)
+        .context("Invalid mcpServers config: expected {\"mcpServers\": {...}}")?;
+    let mut profiles = Vec::new();
+    for (name, server) in file.mcp_servers {
+        let Some(command) = server.command else {
+            eprintln!("{} skipping '{}': only command-based servers can be imported", stderr_label("Warning:", COLOR_WARNING), name);
+            continue;
         };
+        let profile = serde_json::from_value(json!({
+            "command": [command],
+            "default_args": server.args,
+            "env": server.env,
+            "supports_daemon": true,
+        }))?;
+        profiles.push((name, profile));
+    }
+    profiles.sort_by(|a, b| a.0.cmp(&b.0));
 
-        mcp.initialize()?;
-        eprintln!("✅ MCP server ready");
-        Ok(mcp)
+    if let Some((name, _)) = profiles.iter().find(|(name, _)| RESERVED_CONFIG_KEYS.contains(&name.as_str())) {
+        return Err(anyhow!("'{}' is reserved and cannot be used as a server name", name));
     }
+    Ok(profiles)
+}
 
-    fn initialize(&mut self) -> Result<()> {
-        let init_request = json!({
-            "jsonrpc": "2.0",
-            "id": self.next_id(),
-            "method": "initialize",
-            "params": {
-                "protocolVersion": "2025-06-18",
-                "capabilities": {},
-                "clientInfo": {
-                    "name": "mcp-valve",
-                    "version": "1.0.0"
-                }
+/// JSON Schema (draft-07) of the config file. Hand-written; `config_schema_tests` compares
+/// it with the serde fields of `ServerProfile`, `ContainerSpec` and `ToolCallSpec`.
+/// Unknown profile fields are flagged, which catches typos in editors
+fn config_schema() -> Value {
+    let strings = |description: &str| json!({"type": "array", "items": {"type": "string"}, "description": description});
+    let seconds = |description: &str| json!({"type": "integer", "minimum": 0, "description": description});
+    let health_check = json!({
+        "type": "object",
+        "properties": {
+            "tool": {"type": "string"},
+            "args": {"type": "object"},
+            "expect": {"description": "Substring of the result text, or JSON contained in the result value"}
+        },
+        "required": ["tool"],
+        "additionalProperties": false,
+        "description": "Tool call that must succeed before a daemon reports ready"
+    });
+    let container = json!({
+        "type": "object",
+        "properties": {
+            "image": {"type": "string"},
+            "runtime": {"type": "string", "description": "Container CLI (default docker)"},
+            "volumes": strings("-v mounts (template variables expanded)"),
+            "run_args": strings("Extra run arguments placed before the image")
+        },
+        "required": ["image"],
+        "additionalProperties": false,
+        "description": "Run the server from an image instead of on the host"
+    });
+    // Profile fields, in ServerProfile order (a single json! literal this size exceeds the macro recursion limit)
+    let profile_properties: serde_json::Map<String, Value> = [
+        ("command", strings("Server command and its first arguments; with `container`, the command run inside the image")),
+        ("default_args", strings("Arguments appended to `command` unless --server-args replaces them")),
+        ("supports_daemon", json!({"type": "boolean", "description": "Allow start-daemon for this server"})),
+        ("description", json!({"type": "string"})),
+        ("env", json!({"type": "object", "additionalProperties": {"type": "string"}, "description": "Environment variables for the server (${VAR} expanded)"})),
+        ("clear_env", json!({"type": "boolean", "description": "Start the server with an empty environment"})),
+        ("env_passthrough", strings("Host variables copied into the server environment when present")),
+        ("pre_start", strings("Command run before the server starts; a nonzero exit aborts the launch")),
+        ("post_stop", strings("Command run after the daemon stops")),
+        ("warmup", json!({"type": "array", "items": {"$ref": "#/definitions/toolCall"}, "description": "Tool calls run when a daemon starts"})),
+        ("warmup_required", json!({"type": "boolean", "description": "Abort daemon startup if a warmup call fails"})),
+        ("health_check", health_check),
+        ("initialize_params", json!({"type": "object", "description": "Deep-merged into the initialize request params"})),
+        ("require_capabilities", strings("Capabilities the server must declare in initialize, e.g. resources.subscribe")),
+        ("idle_timeout", seconds("Seconds without a request after which the daemon stops")),
+        ("ready_probe", seconds("Seconds to retry tools/list after initialize until it succeeds")),
+        ("request_timeout", seconds("Seconds to wait for the server to answer a request")),
+        ("restart_after_timeouts", json!({"type": "integer", "minimum": 1, "description": "Daemon: restart the server after this many request timeouts in a row"})),
+        ("daemon_start_timeout_secs", seconds("Seconds start-daemon waits for the daemon to become ready (default 60)")),
+        ("cwd", json!({"type": "string", "description": "Working directory for the server (template variables expanded)"})),
+        ("auto_daemon", json!({"type": "boolean", "description": "call starts the daemon when it isn't running"})),
+        ("no_fallback", json!({"type": "boolean", "description": "tui and call --args-stdin-jsonl fail without a running daemon instead of starting the server directly"})),
+        ("allowed_tools", strings("Tools a daemon serves (empty = all)")),
+        ("read_only", json!({"type": "boolean", "description": "Refuse tools matching deny_tools"})),
+        ("deny_tools", strings("Case-insensitive `*` patterns refused in read-only mode")),
+        ("idempotent_tools", strings("Tools call --retry-on-tool-error may retry")),
+        ("tool_defaults", json!({"type": "object", "additionalProperties": {"type": "object"}, "description": "Default arguments per tool name, or \"*\" for all tools"})),
+        ("container", container),
+    ]
+    .into_iter()
+    .map(|(key, schema)| (key.to_string(), schema))
+    .collect();
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "mcp-valve config",
+        "type": "object",
+        "properties": {
+            "$schema": {"type": "string"},
+            "include": strings("Other config files to merge, relative to this file (`*` allowed in the file name)"),
+            "aliases": {
+                "type": "object",
+                "additionalProperties": {"type": "string"},
+                "description": "Short names for servers, e.g. \"pw\": \"playwright\""
             }
-        });
+        },
+        "additionalProperties": {"$ref": "#/definitions/profile"},
+        "definitions": {
+            "toolCall": {
+                "type": "object",
+                "properties": {
+                    "tool": {"type": "string"},
+                    "args": {"type": "object", "description": "Tool arguments (default {})"}
+                },
+                "required": ["tool"],
+                "additionalProperties": false
+            },
+            "profile": {
+                "type": "object",
+                "additionalProperties": false,
+                "properties": profile_properties
+            }
+        }
+    })
+}
 
-        self.send_request(&init_request)?;
+#[cfg(test)]
+mod config_schema_tests {
+    use super::*;
+    use serde::de::{self, Visitor};
 
-        let notification = json!({
-            "jsonrpc": "2.0",
-            "method": "notifications/initialized",
-            "params": {}
-        });
+    /// A deserializer that only records the field names a derived struct asks for
+    struct FieldNames(Vec<&'static str>);
 
-        self.send_notification(&notification)?;
-        Ok(())
+    #[derive(Debug)]
+    struct Captured;
+
+    impl std::fmt::Display for Captured {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("captured")
+        }
     }
 
-    fn send_request(&mut self, request: &Value) -> Result<Value> {
-        let request_str = serde_json::to_string(request)?;
-        writeln!(self.stdin, "{}", request_str)?;
-        self.stdin.flush()?;
+    impl std::error::Error for Captured {}
 
-        let mut line = String::new();
-        self.stdout.read_line(&mut line)?;
+    impl de::Error for Captured {
+        fn custom<T: std::fmt::Display>(_: T) -> Self {
+            Captured
+        }
+    }
 
-        let response: Value = serde_json::from_str(line.trim())
-            .context("Failed to parse JSON-RPC response")?;
+    impl<'de> de::Deserializer<'de> for &mut FieldNames {
+        type Error = Captured;
 
-        if let Some(error) = response.get("error") {
-            return Err(anyhow!("MCP Error: {}", error));
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Captured> {
+            Err(Captured)
         }
 
-        Ok(response)
-    }
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Captured> {
+            self.0.extend(fields);
+            Err(Captured)
+        }
 
-    fn send_notification(&mut self, notification: &Value) -> Result<()> {
-        let notif_str = serde_json::to_string(notification)?;
-        writeln!(self.stdin, "{}", notif_str)?;
-        self.stdin.flush()?;
-        Ok(())
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+            option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+        }
     }
 
-    fn next_id(&mut self) -> u64 {
-        self.request_id += 1;
-        self.request_id
+    fn serde_fields<T: de::DeserializeOwned>() -> Vec<&'static str> {
+        let mut names = FieldNames(Vec::new());
+        let _ = T::deserialize(&mut names);
+        names.0
     }
 
-    fn call_tool(&mut self, name: &str, args: Value) -> Result<Value> {
-        let request = json!({
-            "jsonrpc": "2.0",
-            "id": self.next_id(),
-            "method": "tools/call",
-            "params": {
-                "name": name,
-                "arguments": args
-            }
-        });
+    fn schema_keys(properties: &Value) -> Vec<String> {
+        properties.as_object().unwrap().keys().cloned().collect()
+    }
 
-        let response = match self.send_request(&request) {
-            Ok(resp) => resp,
-            Err(e) => {
-                let error_with_schema = self.format_error_with_schema(name, &e.to_string());
-                return Err(anyhow!("{}", error_with_schema));
-            }
-        };
-        let result = response["result"].clone();
+    fn assert_same_fields<T: de::DeserializeOwned>(properties: &Value) {
+        let mut fields: Vec<String> = serde_fields::<T>().into_iter().map(String::from).collect();
+        fields.sort();
+        assert!(!fields.is_empty());
+        assert_eq!(schema_keys(properties), fields, "config_schema() is out of sync with the Rust type");
+    }
 
-        // Check for tool-level errors (isError field in result)
-        if let Some(is_error) = result.get("isError").and_then(|v| v.as_bool()) {
-            if is_error {
-                // Extract error message from content if available
-                let error_msg = result
-                    .get("content")
-                    .and_then(|c| c.as_array())
-                    .and_then(|arr| arr.first())
-                    .and_then(|item| item.get("text"))
-                    .and_then(|t| t.as_str())
-                    .unwrap_or("Tool execution failed");
+    #[test]
+    fn profile_schema_lists_every_field() {
+        assert_same_fields::<ServerProfile>(&config_schema()["definitions"]["profile"]["properties"]);
+    }
 
-                let error_with_schema =
-                    self.format_error_with_schema(name, &format!("Tool Error: {}", error_msg));
-                return Err(anyhow!("{}", error_with_schema));
-            }
-        }
+    #[test]
+    fn container_schema_lists_every_field() {
+        assert_same_fields::<ContainerSpec>(
+            &config_schema()["definitions"]["profile"]["properties"]["container"]["properties"],
+        );
+    }
 
-        Ok(result)
+    #[test]
+    fn tool_call_schema_lists_every_field() {
+        assert_same_fields::<ToolCallSpec>(&config_schema()["definitions"]["toolCall"]["properties"]);
     }
+}
 
-    fn list_tools(&mut self) -> Result<Value> {
-        let request = json!({
-            "jsonrpc": "2.0",
-            "id": self.next_id(),
-            "method": "tools/list",
-            "params": {}
-        });
+/// Parses `config import` input into (name, profile) pairs
+fn parse_import(content: &str, ndjson: bool) -> Result<Vec<(String, ServerProfile)>> {
+    let profiles: Vec<(String, ServerProfile)> = if ndjson {
+        content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                let record: ProfileRecord = serde_json::from_str(line)
+                    .with_context(|| format!("Invalid profile record on line {}", i + 1))?;
+                Ok((record.name, record.profile))
+            })
+            .collect::<Result<_>>()?
+    } else {
+        let servers: HashMap<String, ServerProfile> = serde_json::from_str(content)
+            .context("Invalid JSON config to import")?;
+        let mut servers: Vec<_> = servers.into_iter().collect();
+        servers.sort_by(|a, b| a.0.cmp(&b.0));
+        servers
+    };
 
-        let response = self.send_request(&request)?;
-        Ok(response["result"].clone())
+    if let Some((name, _)) = profiles.iter().find(|(name, _)| RESERVED_CONFIG_KEYS.contains(&name.as_str())) {
+        return Err(anyhow!("'{}' is reserved and cannot be used as a server name", name));
     }
+    Ok(profiles)
+}
 
-    /// Get the inputSchema for a specific tool
-    fn get_tool_schema(&mut self, tool_name: &str) -> Option<Value> {
-        self.list_tools()
-            .ok()
-            .and_then(|result| result.get("tools").cloned())
-            .and_then(|tools| tools.as_array().cloned())
-            .and_then(|tools| {
-                tools
-                    .into_iter()
-                    .find(|t| t.get("name").and_then(|n| n.as_str()) == Some(tool_name))
-            })
-            .and_then(|tool| tool.get("inputSchema").cloned())
+/// Merges profiles into the config file at `path` (created if missing), keeping its other keys
+fn import_config(path: &Path, profiles: Vec<(String, ServerProfile)>, on_conflict: OnConflict) -> Result<()> {
+    let mut file: serde_json::Map<String, Value> = match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content)
+            .with_context(|| format!("Invalid JSON in config: {}", path.display()))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => serde_json::Map::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read config: {}", path.display())),
+    };
+
+    let conflicts: Vec<&str> = profiles.iter()
+        .map(|(name, _)| name.as_str())
+        .filter(|name| file.contains_key(*name))
+        .collect();
+    if on_conflict == OnConflict::Error && !conflicts.is_empty() {
+        return Err(anyhow!(
+            "Servers already in {}: {}\nUse --on-conflict skip or --on-conflict replace",
+            path.display(),
+            conflicts.join(", ")
+        ));
     }
 
-    /// Format error message with tool schema appended
-    fn format_error_with_schema(&mut self, tool_name: &str, error_msg: &str) -> String {
-        match self.get_tool_schema(tool_name) {
-            Some(schema) => {
-                let schema_str = serde_json::to_string_pretty(&schema)
-                    .unwrap_or_else(|_| schema.to_string());
-                format!(
-                    "{}\n\nSchema for tool '{}':\n{}",
-                    error_msg, tool_name, schema_str
-                )
+    let (mut added, mut replaced, mut skipped) = (0, 0, 0);
+    for (name, profile) in profiles {
+        if file.contains_key(&name) {
+            if on_conflict == OnConflict::Skip {
+                skipped += 1;
+                continue;
             }
-            None => error_msg.to_string(),
+            replaced += 1;
+        } else {
+            added += 1;
         }
+        file.insert(name, serde_json::to_value(&profile)?);
     }
-}
 
-impl Drop for McpClient {
-    fn drop(&mut self) {
-        let _ = self.child.kill();
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
     }
+    fs::write(path, serde_json::to_string_pretty(&file)? + "\n")
+        .with_context(|| format!("Failed to write config: {}", path.display()))?;
+    eprintln!(
+        "Imported into {}: {} added, {} replaced, {} skipped",
+        path.display(),
+        added,
+        replaced,
+        skipped
+    );
+    Ok(())
 }
 
-// ============================================================================
-// Project Context
-// ============================================================================
+/// Opens `path` in the user's editor until it loads as a valid config, or the user
+/// gives up, in which case the previous contents come back and the rejected edit is
+/// kept next to it as `<file>.rejected`
+fn edit_config(path: &Path) -> Result<()> {
+    if !path.exists() {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        fs::write(path, CONFIG_EXAMPLE)
+            .with_context(|| format!("Failed to write config: {}", path.display()))?;
+        eprintln!("Created {} from the example config", path.display());
+    }
+    let previous = fs::read(path)
+        .with_context(|| format!("Failed to read config: {}", path.display()))?;
 
-/// Get the current project path (current working directory)
-fn get_project_path() -> String {
-    std::env::current_dir()
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
         .ok()
-        .and_then(|p| p.to_str().map(|s| s.to_string()))
-        .unwrap_or_else(|| ".".to_string())
-}
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+
+    loop {
+        // Through the shell so editors with arguments work, e.g. EDITOR="code --wait"
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$1\"", editor))
+            .arg("sh")
+            .arg(path)
+            .status()
+            .with_context(|| format!("Failed to run editor: {}", editor))?;
+        if !status.success() {
+            return Err(anyhow!("Editor '{}' exited with {}", editor, status));
+        }
 
-/// Format error message when daemon is not running
-fn daemon_not_running_error(server_name: &str) -> anyhow::Error {
-    let project = get_project_path();
-    anyhow!(
-        "Daemon is not running for project '{}'\n\n\
-        Start daemon with:\n  \
-        cd {}\n  \
-        mcp-valve --server {} start-daemon",
-        project, project, server_name
-    )
+        let error = match load_config_file(path) {
+            Ok(config) => {
+                eprintln!("Saved {} (servers: {})", path.display(), config.servers.len());
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+        eprintln!("{} {:#}", stderr_label("Warning:", COLOR_WARNING), error);
+
+        if std::io::stdin().is_terminal() {
+            eprint!("Re-open the editor? [Y/n] ");
+            std::io::stderr().flush()?;
+            let mut input = String::new();
+            std::io::stdin().read_line(&mut input)?;
+            if !matches!(input.trim().to_lowercase().as_str(), "n" | "no") {
+                continue;
+            }
+        }
+
+        let mut rejected = path.as_os_str().to_owned();
+        rejected.push(".rejected");
+        let rejected = PathBuf::from(rejected);
+        fs::copy(path, &rejected)
+            .with_context(|| format!("Failed to save the rejected edit: {}", rejected.display()))?;
+        fs::write(path, &previous)
+            .with_context(|| format!("Failed to restore config: {}", path.display()))?;
+        return Err(Failure::Config.wrap(anyhow!(
+            "Config left unchanged: {} was restored, the invalid edit is in {}",
+            path.display(),
+            rejected.display()
+        )));
+    }
 }
 
 // ============================================================================
-// Daemon Management
+// CLI Definition
 // ============================================================================
 
-struct DaemonManager {
-    server_name: String,
-    pid_file: PathBuf,
-}
+#[derive(Parser)]
+#[command(name = "mcp-valve")]
+#[command(about = "Unified MCP CLI - Generic MCP Protocol Client")]
+#[command(version)]
+struct Cli {
+    /// Server name from config (e.g., playwright, zen)
+    #[arg(short, long)]
+    server: Option<String>,
 
-impl DaemonManager {
-    fn new(server_name: &str) -> Self {
-        let safe_server_name = sanitize_server_name(server_name);
-        let profile_dir = PathBuf::from(".mcp-profile")
-            .join(&safe_server_name);
+    /// Server arguments replacing the profile's default_args (JSON array, e.g., '["--gui", "--browser", "firefox"]')
+    #[arg(long)]
+    server_args: Option<String>,
 
-        // Ensure profile directory exists with secure permissions (0700)
-        if !profile_dir.exists() {
-            let old_umask = umask(Mode::from_bits_truncate(0o077));
-            fs::create_dir_all(&profile_dir)
-                .expect("Failed to create daemon profile directory");
-            umask(old_umask);
-        }
+    /// Server arguments appended to the profile's default_args (JSON array, e.g., '["--gui"]')
+    #[arg(long, conflicts_with = "server_args")]
+    server_args_append: Option<String>,
 
-        Self {
-            server_name: server_name.to_string(),
-            pid_file: profile_dir.join("daemon.pid"),
-        }
-    }
+    /// Path to config file (overrides default locations)
+    #[arg(short, long, global = true)]
+    config: Option<PathBuf>,
 
-    fn get_socket_path(&self) -> Result<PathBuf> {
-        // Read daemon PID from file
-        let pid_str = fs::read_to_string(&self.pid_file)
-            .context("Failed to read PID file")?;
-        let pid = pid_str.trim();
+    /// Print diagnostic details (e.g. which daemon socket is used) to stderr
+    #[arg(short, long, global = true)]
+    verbose: bool,
 
-        // Socket path includes PID to avoid conflicts
-        Ok(PathBuf::from("/tmp/.mcp").join(format!("{}-{}.sock", self.server_name, pid)))
-    }
+    /// Override a server environment variable (repeatable, e.g. --env DEBUG=1, supports ${VAR})
+    #[arg(long = "env", global = true, value_name = "KEY=VALUE")]
+    env: Vec<String>,
 
-    fn is_running(&self) -> Result<bool> {
-        if !self.pid_file.exists() {
-            return Ok(false);
-        }
+    /// Only serve this tool from the daemon (repeatable; replaces the profile's allowed_tools)
+    #[arg(long = "only-tool", global = true, value_name = "TOOL")]
+    only_tool: Vec<String>,
 
-        let pid_str = fs::read_to_string(&self.pid_file)
-            .context("Failed to read PID file")?;
-        let pid = pid_str.trim().parse::<i32>()
-            .with_context(|| format!("Invalid PID in file: '{}'", pid_str.trim()))?;
+    /// Refuse mutating tools in the daemon (profile deny_tools, else *write*, *delete*, ...)
+    #[arg(long, global = true)]
+    read_only: bool,
 
-        // Check if process exists using kill with signal 0
-        // This doesn't send any signal but checks if process exists and we have permission
-        match kill(Pid::from_raw(pid), None) {
-            Ok(_) => Ok(true),  // Process exists
-            Err(nix::errno::Errno::ESRCH) => Ok(false),  // No such process
-            Err(nix::errno::Errno::EPERM) => Ok(true),   // Process exists but no permission
-            Err(_) => Ok(false),  // Other errors, assume not running
-        }
-    }
+    /// Append server log notifications (notifications/message) to this file as JSON lines
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
 
-    fn start(
-        &self,
-        profile: &ServerProfile,
-        extra_args: Option<Vec<String>>,
-    ) -> Result<()> {
-        if !profile.supports_daemon {
-            return Err(anyhow!(
-                "Server '{}' does not support daemon mode (supports_daemon: false)",
-                self.server_name
-            ));
-        }
+    /// Record every JSON-RPC frame (all hops, secrets redacted) to this file as JSON lines
+    #[arg(long, global = true)]
+    debug_transcript: Option<PathBuf>,
 
-        if self.is_running()? {
-            return Err(anyhow!("Daemon already running for '{}'", self.server_name));
-        }
+    /// Print results as compact single-line JSON (faster for large results; no colors)
+    #[arg(long, global = true)]
+    compact: bool,
 
-        let project = get_project_path();
-        eprintln!("Project: {}", project);
-        eprintln!("Profile: {}", self.pid_file.parent().unwrap().display());
-        eprintln!("Starting MCP daemon for '{}'...", self.server_name);
+    /// Fail when a server name is defined in more than one config file (includes or
+    /// MCP_VALVE_CONFIG_PATH layers) instead of warning
+    #[arg(long, global = true, env = "MCP_VALVE_STRICT_CONFIG", value_parser = clap::builder::FalseyValueParser::new())]
+    strict_config: bool,
 
-        // Build daemon command
-        let mut cmd = Command::new(std::env::current_exe()?);
-        cmd.arg("__internal_daemon");
-        cmd.arg("--server");
-        cmd.arg(&self.server_name);
+    /// Fail when no daemon is running instead of starting the server directly
+    /// (tui, call --args-stdin-jsonl)
+    #[arg(long, global = true)]
+    no_fallback: bool,
 
-        if let Some(ref args) = extra_args {
-            cmd.arg("--server-args");
-            cmd.arg(serde_json::to_string(args)?);
-        }
+    /// Append the last lines of daemon.log to errors from daemon requests
+    #[arg(long, global = true, env = "MCP_VALVE_SHOW_DAEMON_LOGS", value_parser = clap::builder::FalseyValueParser::new())]
+    show_daemon_logs: bool,
 
-        // Create log file for daemon stderr
-        let profile_dir = self.pid_file.parent().unwrap();
-        let log_file = std::fs::File::create(profile_dir.join("daemon.log"))
-            .context("Failed to create daemon log file")?;
+    /// Ask the daemon to gzip large responses (also MCP_VALVE_COMPRESS=1)
+    #[arg(long, global = true, env = "MCP_VALVE_COMPRESS", value_parser = clap::builder::FalseyValueParser::new())]
+    compress: bool,
 
-        // Fork daemon process with proper daemonization
-        let child = unsafe {
-            cmd.pre_exec(|| {
-                // Create new session to detach from controlling terminal
-                setsid().map_err(|e| std::io::Error::from_raw_os_error(e as i32))?;
-                Ok(())
-            })
-        }
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::from(log_file))
-        .spawn()
-        .context("Failed to spawn daemon process")?;
+    /// Seconds to wait for the daemon socket to accept a connection
+    #[arg(long, global = true, env = "MCP_VALVE_TIMEOUT_CONNECT", default_value = "2", value_parser = parse_seconds)]
+    timeout_connect: Duration,
 
-        let child_pid = child.id();
+    /// Seconds to wait for a daemon response
+    #[arg(long, global = true, env = "MCP_VALVE_TIMEOUT_READ", default_value = "30", value_parser = parse_seconds)]
+    timeout_read: Duration,
 
-        // Write PID file
+    /// Seconds to wait while sending a request to the daemon
+    #[arg(long, global = true, env = "MCP_VALVE_TIMEOUT_WRITE", default_value = "30", value_parser = parse_seconds)]
+    timeout_write: Duration,
+
+    /// Fail instead of warning when the server answers with another protocol version
+    #[arg(long, global = true)]
+    strict_protocol: bool,
+
+    /// After initialize, retry tools/list for up to this many seconds until the server is ready
+    #[arg(long, global = true, value_name = "SECS", value_parser = parse_seconds)]
+    wait_ready: Option<Duration>,
+
+    /// How long start-daemon waits for the daemon to become ready (default 60)
+    #[arg(long, global = true, value_name = "SECS", value_parser = parse_seconds)]
+    start_timeout: Option<Duration>,
+
+    /// Keep daemon.log across daemon starts instead of truncating it, with a separator
+    /// line per start; a log over 10 MiB is moved to daemon.log.1 first
+    #[arg(long, global = true, env = "MCP_VALVE_APPEND_LOG", value_parser = clap::builder::FalseyValueParser::new())]
+    append_log: bool,
+
+    /// First JSON-RPC id to use, for telling invocations apart in transcripts (default 1)
+    #[arg(long, global = true, value_name = "N")]
+    json_rpc_id_start: Option<u64>,
+
+    /// Print the environment the server would be launched with and exit (`call`, `start-daemon`)
+    #[arg(long, global = true)]
+    dump_env: bool,
+
+    /// Don't redact secret-looking values in --dump-env output
+    #[arg(long, global = true, requires = "dump_env")]
+    show_secrets: bool,
+
+    /// Kill the server if it doesn't answer a request within this many seconds (overrides `request_timeout`)
+    #[arg(long, global = true, value_name = "SECS", value_parser = parse_seconds)]
+    request_timeout: Option<Duration>,
+
+    /// Servers to start or query at once in `index` / `search-tools` (default: CPU count)
+    #[arg(long, global = true, value_name = "N")]
+    jobs: Option<std::num::NonZeroUsize>,
+
+    /// Kill the server if one response exceeds this many bytes (default 16 MiB)
+    #[arg(long, global = true, env = "MCP_VALVE_MAX_RESPONSE_BYTES", value_name = "N")]
+    max_response_bytes: Option<usize>,
+
+    /// When to color output: auto (terminals only, honoring NO_COLOR, CLICOLOR and
+    /// CLICOLOR_FORCE), always or never
+    #[arg(long, global = true, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Same as --color never
+    #[arg(long, global = true)]
+    no_color: bool,
+
+    /// Page results through $PAGER even when they fit the terminal
+    #[arg(long, global = true, conflicts_with = "no_pager")]
+    pager: bool,
+
+    /// Never page results (by default, results taller than the terminal are paged)
+    #[arg(long, global = true)]
+    no_pager: bool,
+
+    /// How to report a failure on stderr; json also maps JSON-RPC codes to exit codes
+    #[arg(long, global = true, value_enum, default_value_t = ErrorFormat::Text)]
+    error_format: ErrorFormat,
+
+    /// Write the result of call/list-tools to this file instead of stdout
+    #[arg(short, long, global = true)]
+    out: Option<PathBuf>,
+
+    /// Run servers in this directory, overriding the profile's cwd (also sets {cwd})
+    #[arg(long, global = true, env = "MCP_VALVE_WORKING_DIR")]
+    working_dir: Option<PathBuf>,
+
+    /// Anchor profile dirs (PID, logs, {profile_dir}) here instead of ./.mcp-profile
+    #[arg(long, global = true, env = "MCP_VALVE_PROFILE_DIR")]
+    profile_dir: Option<PathBuf>,
+
+    /// Run or target a named daemon instance, so several can coexist per server
+    #[arg(long, global = true, env = "MCP_VALVE_INSTANCE")]
+    instance: Option<String>,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the loaded profiles (a JSON config, or NDJSON with --ndjson)
+    Export {
+        /// One {"name": ..., "profile": {...}} object per line
+        #[arg(long)]
+        ndjson: bool,
+        /// Config format to write; `mcp-servers` is the {"mcpServers": ...} shape other
+        /// MCP clients (e.g. Claude Desktop) read. Honors --out
+        #[arg(long, value_enum, default_value_t = ConfigFormat::Valve, conflicts_with = "ndjson")]
+        format: ConfigFormat,
+    },
+    /// Print the JSON Schema of the config file, for editors (`"$schema"`). Honors --out
+    Schema,
+    /// Open the config file in $VISUAL / $EDITOR and check it after saving
+    ///
+    /// Creates the file from an example when it doesn't exist. An invalid edit can be
+    /// re-opened; otherwise the previous version is restored.
+    Edit,
+    /// Merge profiles into the config file
+    Import {
+        /// File to import ("-" for stdin)
+        #[arg(default_value = "-")]
+        file: String,
+        /// Input has one {"name": ..., "profile": {...}} object per line
+        #[arg(long)]
+        ndjson: bool,
+        /// Config format to read, e.g. a Claude Desktop config with `mcp-servers`
+        #[arg(long, value_enum, default_value_t = ConfigFormat::Valve, conflicts_with = "ndjson")]
+        format: ConfigFormat,
+        /// What to do when a profile name already exists in the config file
+        #[arg(long, value_enum, default_value_t = OnConflict::Error)]
+        on_conflict: OnConflict,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ConfigFormat {
+    /// mcp-valve profiles
+    Valve,
+    /// {"mcpServers": {"<name>": {"command", "args", "env"}}}
+    #[value(alias = "claude")]
+    McpServers,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum OnConflict {
+    /// Abort without changing the file
+    Error,
+    /// Keep the existing profile
+    Skip,
+    /// Overwrite the existing profile
+    Replace,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ErrorFormat {
+    /// `Error: <message>`
+    Text,
+    /// `{"error":{"code":...,"message":...,"data":...}}`
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ColorChoice {
+    /// Color terminals unless the environment says otherwise
+    Auto,
+    /// Color even when piped (overrides NO_COLOR)
+    Always,
+    /// Never emit color escape codes
+    Never,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// List all configured servers
+    ListServers,
+
+    /// Export or import server profiles
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+
+    /// Call any MCP tool
+    #[command(group = clap::ArgGroup::new("line_input").args(["stream", "args_stdin_jsonl"]))]
+    Call {
+        /// Tool name (e.g., browser_navigate, chat)
+        #[arg(required_unless_present_any = ["stream", "dump_env"])]
+        tool: Option<String>,
+        /// Arguments as JSON string
+        #[arg(short, long, default_value = "{}")]
+        args: String,
+        /// Print the result as one compact JSON Lines record tagged with tool and status
+        #[arg(long)]
+        jsonl: bool,
+        /// Execute the call N times and report success counts and latency percentiles
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        repeat: u32,
+        /// Number of repeated calls to run in parallel
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        concurrency: u32,
+        /// Suppress individual results when repeating (summary only)
+        #[arg(short, long)]
+        quiet: bool,
+        /// Rerun the call whenever this file or directory changes
+        #[arg(long, conflicts_with = "repeat")]
+        watch: Option<PathBuf>,
+        /// Read `<tool> [json_args]` lines from stdin and print one JSON Lines record each
+        #[arg(long, conflicts_with_all = ["tool", "watch", "repeat"])]
+        stream: bool,
+        /// Call the tool once per stdin line, each line a JSON arguments object, and
+        /// print one indexed JSON Lines record each (starts the server if no daemon runs)
+        #[arg(long, conflicts_with_all = ["args", "watch", "repeat", "stream"])]
+        args_stdin_jsonl: bool,
+        /// With --stream or --args-stdin-jsonl, stop at the first invalid line or failed call
+        #[arg(long, requires = "line_input")]
+        fail_fast: bool,
+        /// Print only this part of the result: `.content[0].text`, `$.a.b` or a JSON pointer `/a/b`
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["jsonl", "repeat", "watch", "stream", "args_stdin_jsonl"])]
+        select: Option<String>,
+        /// Print the result through a template: `{{.path.to.field}}` placeholders are
+        /// replaced with values from the result, e.g. "Title: {{.content.0.text}}"
+        #[arg(long, value_name = "TEMPLATE", conflicts_with_all = ["select", "jsonl", "repeat", "watch", "stream", "args_stdin_jsonl"])]
+        output_template: Option<String>,
+        /// With --output-template, fail on a placeholder that matches nothing instead of leaving it empty
+        #[arg(long, requires = "output_template")]
+        strict: bool,
+        /// Build the arguments from these values, assigned to the tool's inputSchema
+        /// properties in order and converted to their declared types
+        #[arg(long, value_name = "VALUE", num_args = 1.., allow_negative_numbers = true,
+              conflicts_with_all = ["args", "stream", "args_stdin_jsonl"])]
+        positional: Option<Vec<String>>,
+        /// Set argument NAME to a file's path instead of its data (repeatable);
+        /// template variables like {profile_dir} are expanded and the path made absolute
+        #[arg(long, value_name = "NAME=@PATH", conflicts_with_all = ["stream", "args_stdin_jsonl"])]
+        arg_file: Vec<String>,
+        /// Set argument NAME to a file's contents as a string (repeatable)
+        #[arg(long, value_name = "NAME=@PATH", conflicts_with_all = ["stream", "args_stdin_jsonl"])]
+        arg_file_contents: Vec<String>,
+        /// Retry a tool-level error (`isError`) up to N times with backoff; the tool
+        /// must be listed with --idempotent-tool or in the profile's idempotent_tools
+        #[arg(long, value_name = "N", default_value_t = 0, conflicts_with_all = ["repeat", "watch", "stream", "args_stdin_jsonl"])]
+        retry_on_tool_error: u32,
+        /// Tool that is safe to call again after a failure (repeatable)
+        #[arg(long, value_name = "NAME", requires = "retry_on_tool_error")]
+        idempotent_tool: Vec<String>,
+        /// Start an ephemeral daemon if none is running, even for servers without
+        /// supports_daemon, so later calls reuse it; it stops after SECS idle (default 60)
+        #[arg(long, value_name = "SECS", num_args = 0..=1, default_missing_value = "60")]
+        keep_alive: Option<u64>,
+        /// Start the server's daemon first if it isn't running (like the profile's auto_daemon)
+        #[arg(long, conflicts_with = "keep_alive")]
+        auto_daemon: bool,
+    },
+
+    /// Run several tool calls as one JSON-RPC batch
+    ///
+    /// Input is a JSON array of {"tool": "...", "args": {...}} objects.
+    Batch {
+        /// File with the batch definition ("-" for stdin)
+        #[arg(default_value = "-")]
+        file: String,
+        /// Print one compact JSON Lines record per call instead of a JSON array
+        #[arg(long)]
+        jsonl: bool,
+    },
+
+    /// List all available tools from the server
+    ListTools {
+        /// Print only the number of tools
+        #[arg(long)]
+        count: bool,
+        /// With --count, count the tools of every server with a running daemon
+        #[arg(long, requires = "count")]
+        all: bool,
+    },
+
+    /// Show a tool's description, inputSchema and outputSchema
+    DescribeTool {
+        /// Tool name
+        name: String,
+    },
+
+    /// Complete an argument value of a prompt or resource template
+    ///
+    /// Prints one candidate per line. Requires the server's `completions` capability.
+    Complete {
+        /// What to complete: prompt:<name> or resource:<uri template>
+        reference: String,
+        /// Argument and partial value, as name=partial
+        argument: String,
+    },
+
+    /// Compare the server's tools against a saved snapshot
+    ///
+    /// Exits non-zero if tools were added, removed, or changed their inputSchema.
+    ToolsDiff {
+        /// Snapshot to compare against (default: <profile_dir>/tools.json)
+        #[arg(long)]
+        baseline: Option<PathBuf>,
+        /// Save the current tools as the new snapshot
+        #[arg(long)]
+        update: bool,
+    },
+
+    /// Build or show the tools index of every configured server
+    ///
+    /// Starts each server, collects its tools, and writes tools-index.json next to
+    /// the config file. Servers that fail or time out are skipped.
+    Index {
+        /// Rebuild the index (it is built when missing either way)
+        #[arg(long)]
+        refresh: bool,
+        /// Seconds to wait for each server to answer
+        #[arg(long, default_value = "10", value_parser = parse_seconds)]
+        timeout: Duration,
+    },
+
+    /// Search tool names and descriptions across servers
+    ///
+    /// Uses tools-index.json when present, otherwise the running daemons.
+    SearchTools {
+        /// Case-insensitive text to look for
+        query: String,
+    },
+
+    /// Send an arbitrary JSON-RPC method and print the server's raw response
+    ///
+    /// An escape hatch for methods the CLI doesn't model. Errors returned by the
+    /// server are printed as part of the response rather than failing the command.
+    RawRequest {
+        /// Method name (e.g., ping, logging/setLevel)
+        method: String,
+        /// Params as JSON string
+        #[arg(default_value = "{}")]
+        params: String,
+        /// Send as a notification (no id, no response)
+        #[arg(long)]
+        notification: bool,
+        /// Let the daemon handle the method as its own (tool_defaults, allowed_tools
+        /// filtering), print only the `result` and fail on an error response
+        #[arg(long, conflicts_with = "notification")]
+        result: bool,
+    },
+
+    /// Run a JSON recipe of tool calls and daemon starts, in order
+    ///
+    /// A step can bind parts of a call's result to variables that later steps use
+    /// as ${name} in args or in a started daemon's env.
+    Run {
+        /// Recipe file ("-" for stdin)
+        file: String,
+    },
+
+    /// Interactive shell mode
+    Shell,
+
+    /// Browse tools and their input schemas in a terminal UI, and call them from a form
+    ///
+    /// Uses the server's daemon when one is running, otherwise starts the server for
+    /// the session. Prints the tool list instead when not attached to a terminal.
+    Tui,
+
+    /// Start background daemon (requires supports_daemon: true)
+    StartDaemon {
+        /// Stop a daemon that is already running and start a fresh one instead of failing
+        #[arg(long)]
+        replace_running: bool,
+    },
+
+    /// Stop background daemon
+    StopDaemon,
+
+    /// Stop and start a daemon with the config, --server-args, --env and options it was started with
+    RestartDaemon,
+
+    /// SIGKILL a wedged daemon and its server immediately and clean up its files
+    ///
+    /// Not graceful by design: no SIGTERM grace period and no post_stop hook.
+    ResetDaemon,
+
+    /// Check daemon status
+    DaemonStatus,
+
+    /// Remove stale PID files and sockets left by crashed daemons
+    ///
+    /// Scans this project's profile dirs and /tmp/.mcp. Running daemons are never touched.
+    Prune {
+        /// Also delete the profile dirs of dead daemons and of servers no longer in the config
+        #[arg(long)]
+        all: bool,
+        /// List what would be removed without removing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Show CLI version (and server version with --server)
+    Version,
+
+    /// Start a server, complete the MCP handshake and print its serverInfo and capabilities
+    ///
+    /// Calls no tools and ignores any running daemon; exits non-zero if the server
+    /// fails to start or initialize (or to answer ping, with --ping).
+    Check {
+        /// Also send a `ping` request after initializing
+        #[arg(long)]
+        ping: bool,
+    },
+
+    /// Follow a running daemon's log with live uptime and request stats
+    Attach,
+
+    /// Print the end of a daemon's log (or another log file)
+    Logs {
+        /// Show at most this many lines (default 50, or all matching lines with --since)
+        #[arg(short = 'n', long)]
+        lines: Option<usize>,
+        /// Only lines logged within this long, e.g. 90s, 10m, 1h, 2d
+        #[arg(long, value_parser = parse_age)]
+        since: Option<Duration>,
+        /// Read this file instead of the daemon's daemon.log (e.g. a --log-file JSONL log)
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+
+    /// Subscribe to a resource and print update notifications until Ctrl-C
+    ///
+    /// Starts its own server process, since the daemon serves one request at a time.
+    Subscribe {
+        /// Resource URI to watch
+        uri: String,
+        /// Re-read and print the resource on every update
+        #[arg(long)]
+        read: bool,
+    },
+}
+
+// ============================================================================
+// Template Variable Expansion
+// ============================================================================
+
+/// Sanitizes server name to prevent path traversal attacks
+///
+/// Only allows alphanumeric characters, hyphens, and underscores
+fn sanitize_server_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
+        .collect()
+}
+
+/// `--profile-dir`, made absolute
+static PROFILE_ROOT: OnceLock<PathBuf> = OnceLock::new();
+/// `--working-dir`, made absolute
+static WORKING_DIR: OnceLock<PathBuf> = OnceLock::new();
+/// `--instance`
+static INSTANCE: OnceLock<String> = OnceLock::new();
+
+/// Variables that carry `--profile-dir`, `--working-dir` and `--instance` to the daemon.
+/// Only the `__internal_daemon` spawn sets them; servers and hooks never inherit them.
+const DAEMON_SCOPE_ENV: [&str; 3] = ["MCP_VALVE_PROFILE_DIR", "MCP_VALVE_WORKING_DIR", "MCP_VALVE_INSTANCE"];
+
+/// The scope this process runs in, as `DAEMON_SCOPE_ENV` pairs for the daemon's environment
+fn daemon_scope_env() -> Vec<(&'static str, std::ffi::OsString)> {
+    let values = [
+        PROFILE_ROOT.get().map(|dir| dir.as_os_str().to_owned()),
+        WORKING_DIR.get().map(|dir| dir.as_os_str().to_owned()),
+        INSTANCE.get().map(Into::into),
+    ];
+    DAEMON_SCOPE_ENV.into_iter().zip(values)
+        .filter_map(|(name, value)| Some((name, value?)))
+        .collect()
+}
+
+/// In the daemon: adopts the scope `start` passed in the environment, then drops the
+/// variables so nothing the daemon spawns sees them
+fn adopt_daemon_scope_env() {
+    if let Some(dir) = std::env::var_os("MCP_VALVE_PROFILE_DIR") {
+        let _ = PROFILE_ROOT.set(std::path::absolute(&dir).unwrap_or_else(|_| PathBuf::from(dir)));
+    }
+    if let Some(dir) = std::env::var_os("MCP_VALVE_WORKING_DIR") {
+        let _ = WORKING_DIR.set(PathBuf::from(dir));
+    }
+    if let Ok(instance) = std::env::var("MCP_VALVE_INSTANCE") {
+        let _ = INSTANCE.set(instance);
+    }
+    for name in DAEMON_SCOPE_ENV {
+        std::env::remove_var(name);
+    }
+}
+
+/// Root of the per-server profile dirs: `--profile-dir` or `.mcp-profile` relative to
+/// the current directory
+fn profile_root() -> PathBuf {
+    PROFILE_ROOT.get().cloned().unwrap_or_else(|| PathBuf::from(".mcp-profile"))
+}
+
+/// Daemon instance selected with `--instance`
+fn daemon_instance() -> Option<String> {
+    INSTANCE.get()
+        .map(|i| sanitize_server_name(i))
+        .filter(|i| !i.is_empty())
+}
+
+/// Name of a server's daemon: `server`, or `server@instance` for a named instance
+fn daemon_label(server_name: &str) -> String {
+    let name = sanitize_server_name(server_name);
+    match daemon_instance() {
+        Some(instance) => format!("{}@{}", name, instance),
+        None => name,
+    }
+}
+
+/// Profile directory of a server (name sanitized)
+fn server_profile_dir(server_name: &str) -> PathBuf {
+    profile_root().join(daemon_label(server_name))
+}
+
+/// Expands template variables in argument strings
+///
+/// Supported variables:
+/// - {profile_dir}: <profile root>/<server-name> (sanitized), see `profile_root`
+/// - {pid}: Process ID
+/// - {cwd}: The server's working directory, see `server_working_dir`
+///
+/// Security: Server names are sanitized to prevent path traversal
+fn expand_template_vars(arg: &str, server_name: &str, cwd: &Path) -> String {
+    let profile_dir = server_profile_dir(server_name);
+    let profile_dir_str = profile_dir.to_str().unwrap_or("");
+    let pid = std::process::id().to_string();
+
+    arg.replace("{profile_dir}", profile_dir_str)
+        .replace("{pid}", &pid)
+        .replace("{cwd}", cwd.to_str().unwrap_or("."))
+}
+
+/// Directory a server runs in: `--working-dir`, else the profile's `cwd` (template
+/// vars expanded, relative to the current directory), else the current directory
+fn server_working_dir(profile: &ServerProfile, server_name: &str) -> PathBuf {
+    let current = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    if let Some(dir) = WORKING_DIR.get() {
+        return dir.clone();
+    }
+    match &profile.cwd {
+        Some(dir) => current.join(expand_template_vars(dir, server_name, &current)),
+        None => current,
+    }
+}
+
+/// Expands `${VAR}` references using the current environment (unset vars expand to "")
+fn expand_env_refs(value: &str) -> String {
+    expand_refs(value, |name| std::env::var(name).ok())
+}
+
+/// Expands `${NAME}` references with `lookup` (unknown names expand to "")
+fn expand_refs(value: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        match rest[start + 2..].find('}') {
+            Some(end) => {
+                let name = &rest[start + 2..start + 2 + end];
+                result.push_str(&lookup(name).unwrap_or_default());
+                rest = &rest[start + 2 + end + 1..];
+            }
+            None => {
+                result.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// Expands `${env:VAR}` and `${env:VAR:-default}` in a tool's JSON arguments before
+/// they are parsed. Values are JSON-escaped, so they are safe inside string literals;
+/// the default is used when VAR is unset or empty, and an unset VAR without one is an error.
+/// `$${env:...}` stands for a literal `${env:...}`
+fn expand_args_env(json: &str) -> Result<String> {
+    let mut result = String::with_capacity(json.len());
+    let mut rest = json;
+
+    while let Some(start) = rest.find("${env:") {
+        let token = &rest[start + "${env:".len()..];
+        if let Some(before) = rest[..start].strip_suffix('$') {
+            result.push_str(before);
+            result.push_str("${env:");
+            rest = token;
+            continue;
+        }
+        result.push_str(&rest[..start]);
+        let end = token.find('}')
+            .ok_or_else(|| Failure::Config.wrap(anyhow!("Unterminated ${{env:...}} in arguments")))?;
+        let (name, default) = match token[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&token[..end], None),
+        };
+        let value = match (std::env::var(name), default) {
+            (Ok(value), None) => value,
+            (Ok(value), Some(_)) if !value.is_empty() => value,
+            (_, Some(default)) => default.to_string(),
+            (Err(_), None) => return Err(Failure::Config.wrap(anyhow!(
+                "Environment variable {} is not set (use ${{env:{}:-default}} for a fallback)",
+                name,
+                name
+            ))),
+        };
+        let escaped = serde_json::to_string(&value)?;
+        result.push_str(&escaped[1..escaped.len() - 1]);
+        rest = &token[end + 1..];
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod args_env_tests {
+    use super::*;
+
+    #[test]
+    fn default_applies_when_unset_or_empty() {
+        std::env::remove_var("MCP_VALVE_TEST_UNSET");
+        std::env::set_var("MCP_VALVE_TEST_EMPTY", "");
+        assert_eq!(expand_args_env(r#"{"a":"${env:MCP_VALVE_TEST_UNSET:-x}"}"#).unwrap(), r#"{"a":"x"}"#);
+        assert_eq!(expand_args_env(r#"{"a":"${env:MCP_VALVE_TEST_EMPTY:-x}"}"#).unwrap(), r#"{"a":"x"}"#);
+    }
+
+    #[test]
+    fn empty_is_kept_but_unset_is_an_error() {
+        std::env::remove_var("MCP_VALVE_TEST_MISSING");
+        std::env::set_var("MCP_VALVE_TEST_BLANK", "");
+        assert_eq!(expand_args_env(r#"{"a":"${env:MCP_VALVE_TEST_BLANK}"}"#).unwrap(), r#"{"a":""}"#);
+        let err = expand_args_env(r#"{"a":"${env:MCP_VALVE_TEST_MISSING}"}"#).unwrap_err();
+        assert_eq!(Failure::of(&err), Some(Failure::Config));
+    }
+
+    #[test]
+    fn values_are_json_escaped() {
+        std::env::set_var("MCP_VALVE_TEST_QUOTED", "say \"hi\"\n\\");
+        let json = expand_args_env(r#"{"a":"${env:MCP_VALVE_TEST_QUOTED}"}"#).unwrap();
+        let value: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["a"], "say \"hi\"\n\\");
+    }
+
+    #[test]
+    fn unterminated_token_is_an_error() {
+        let err = expand_args_env(r#"{"a":"${env:MCP_VALVE_TEST_OPEN"}"#).unwrap_err();
+        assert_eq!(Failure::of(&err), Some(Failure::Config));
+    }
+
+    #[test]
+    fn doubled_dollar_keeps_the_token_literal() {
+        let json = r#"{"template":"$${env:HOME} and $${env:unterminated"}"#;
+        assert_eq!(expand_args_env(json).unwrap(), r#"{"template":"${env:HOME} and ${env:unterminated"}"#);
+    }
+}
+
+/// The server's argument list from `--server-args` (replaces `default_args`) or
+/// `--server-args-append` (added after them); `None` means the profile's defaults
+fn parse_server_args(
+    replace: Option<&str>,
+    append: Option<&str>,
+    profile: &ServerProfile,
+) -> Result<Option<Vec<String>>> {
+    if let Some(args) = replace {
+        let args = serde_json::from_str::<Vec<String>>(args).context("Invalid JSON in --server-args")?;
+        return Ok(Some(args));
+    }
+    let Some(args) = append else {
+        return Ok(None);
+    };
+    let args = serde_json::from_str::<Vec<String>>(args).context("Invalid JSON in --server-args-append")?;
+    Ok(Some(profile.default_args.iter().cloned().chain(args).collect()))
+}
+
+/// Parses repeated `--env KEY=VALUE` flags into a map, expanding `${VAR}` in values
+fn parse_env_overrides(pairs: &[String]) -> Result<HashMap<String, String>> {
+    Ok(split_env_pairs(pairs)?
+        .into_iter()
+        .map(|(key, value)| (key, expand_env_refs(&value)))
+        .collect())
+}
+
+/// Parses `KEY=VALUE` pairs as given. The daemon reads its `--env` flags this way:
+/// the client expanded them already, and a second pass would mangle a value that
+/// expanded to a literal `${...}`
+fn split_env_pairs(pairs: &[String]) -> Result<HashMap<String, String>> {
+    pairs
+        .iter()
+        .map(|pair| {
+            let (key, value) = pair
+                .split_once('=')
+                .filter(|(key, _)| !key.is_empty())
+                .ok_or_else(|| anyhow!("Invalid --env '{}': expected KEY=VALUE", pair))?;
+            Ok((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod env_override_tests {
+    use super::*;
+
+    #[test]
+    fn forwarded_values_are_not_expanded_again() {
+        // What the client forwards after expanding `--env TOKEN=${SECRET}` with SECRET='a${HOME}b'
+        let forwarded = ["TOKEN=a${HOME}b".to_string()];
+        assert_eq!(split_env_pairs(&forwarded).unwrap()["TOKEN"], "a${HOME}b");
+    }
+
+    #[test]
+    fn invalid_pairs_are_rejected() {
+        assert!(split_env_pairs(&["=x".to_string()]).is_err());
+        assert!(split_env_pairs(&["NOVALUE".to_string()]).is_err());
+    }
+}
+
+/// Sets a profile's environment on a command: passthrough first, explicit env wins.
+/// mcp-valve's own scope variables are removed unless the profile names them.
+fn apply_profile_env(cmd: &mut Command, profile: &ServerProfile) {
+    if profile.clear_env {
+        cmd.env_clear();
+    }
+    for name in DAEMON_SCOPE_ENV {
+        cmd.env_remove(name);
+    }
+    for name in &profile.env_passthrough {
+        if let Ok(value) = std::env::var(name) {
+            cmd.env(name, value);
+        }
+    }
+    for (key, value) in &profile.env {
+        cmd.env(key, value);
+    }
+}
+
+/// The environment a server started from `profile` gets: the inherited one (unless
+/// `clear_env`) with `apply_profile_env` applied, exactly as at spawn
+fn effective_env(profile: &ServerProfile) -> std::collections::BTreeMap<String, String> {
+    let mut cmd = Command::new("true");
+    apply_profile_env(&mut cmd, profile);
+
+    let mut env: std::collections::BTreeMap<String, String> = if profile.clear_env {
+        std::collections::BTreeMap::new()
+    } else {
+        std::env::vars().collect()
+    };
+    for (key, value) in cmd.get_envs() {
+        let key = key.to_string_lossy().into_owned();
+        match value {
+            Some(value) => env.insert(key, value.to_string_lossy().into_owned()),
+            None => env.remove(&key),
+        };
+    }
+    env
+}
+
+/// `--dump-env`: prints the server's launch environment as KEY=value lines,
+/// secret-looking values redacted unless `show_secrets`
+fn print_env_dump(profile: &ServerProfile, show_secrets: bool) {
+    for (key, value) in effective_env(profile) {
+        if is_secret_key(&key) && !show_secrets {
+            println!("{}=[REDACTED]", key);
+        } else {
+            println!("{}={}", key, value);
+        }
+    }
+}
+
+/// Runs a `pre_start`/`post_stop` hook with template expansion and the profile's env.
+/// A nonzero exit is an error carrying the hook's stderr.
+fn run_hook(kind: &str, hook: &[String], profile: &ServerProfile, server_name: &str) -> Result<()> {
+    if hook.is_empty() {
+        return Ok(());
+    }
+
+    let cwd = server_working_dir(profile, server_name);
+    let args: Vec<String> = hook.iter().map(|arg| expand_template_vars(arg, server_name, &cwd)).collect();
+    eprintln!("Running {} hook: {:?}", kind, args);
+
+    let mut cmd = Command::new(&args[0]);
+    cmd.args(&args[1..]);
+    apply_profile_env(&mut cmd, profile);
+
+    let output = cmd
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("Failed to run {} hook: {:?}", kind, args))?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{} hook failed ({}): {}",
+            kind,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+// ============================================================================
+// Logging
+// ============================================================================
+
+/// Current UTC time as an RFC 3339 timestamp (e.g. 2025-06-18T12:34:56.789Z)
+fn timestamp_rfc3339() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = now.as_secs();
+    let rem = secs % 86_400;
+
+    // Civil date from days since epoch (Howard Hinnant's algorithm)
+    let z = (secs / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        now.subsec_millis()
+    )
+}
+
+/// Opens a file for appending JSON lines, creating parent directories as needed
+fn open_append(path: &PathBuf) -> Result<fs::File> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open log file: {}", path.display()))
+}
+
+/// Debug transcripts rotate to `<path>.1` once they reach this size
+const TRANSCRIPT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+/// An appended `daemon.log` is rotated to `daemon.log.1` at start once it reaches this size
+const DAEMON_LOG_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Moves `path` to `<path>.1`, replacing an older backup
+fn rotate_log(path: &Path) {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".1");
+    let _ = fs::rename(path, backup);
+}
+
+struct Transcript {
+    path: PathBuf,
+    file: fs::File,
+}
+
+/// Set by `--debug-transcript`; shared by the main and stdout reader threads
+static TRANSCRIPT: OnceLock<Mutex<Transcript>> = OnceLock::new();
+
+fn open_transcript(path: &Path) -> Result<()> {
+    let path = path.to_path_buf();
+    let file = open_append(&path)?;
+    let _ = TRANSCRIPT.set(Mutex::new(Transcript { path, file }));
+    Ok(())
+}
+
+/// Keys whose values are replaced in transcripts
+fn is_secret_key(key: &str) -> bool {
+    const SECRET_WORDS: [&str; 9] = [
+        "token", "secret", "password", "passwd", "authorization", "api_key", "apikey", "credential", "cookie",
+    ];
+    let key = key.to_lowercase();
+    SECRET_WORDS.iter().any(|word| key.contains(word))
+}
+
+fn redact(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| {
+                    let value = match value {
+                        Value::String(_) | Value::Number(_) if is_secret_key(key) => json!("[REDACTED]"),
+                        _ => redact(value),
+                    };
+                    (key.clone(), value)
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.iter().map(redact).collect()),
+        _ => value.clone(),
+    }
+}
+
+/// Records one JSON-RPC frame in the debug transcript, if enabled. `peer` is the
+/// other end (`server`, `daemon` or `client`) and `direction` is `send` or `recv`.
+fn record_frame(peer: &str, direction: &str, message: &Value) {
+    let Some(transcript) = TRANSCRIPT.get() else {
+        return;
+    };
+    let Ok(mut transcript) = transcript.lock() else {
+        return;
+    };
+
+    if transcript.file.metadata().is_ok_and(|m| m.len() >= TRANSCRIPT_MAX_BYTES) {
+        rotate_log(&transcript.path);
+        if let Ok(file) = open_append(&transcript.path) {
+            transcript.file = file;
+        }
+    }
+
+    let record = json!({
+        "timestamp": timestamp_rfc3339(),
+        "pid": std::process::id(),
+        "peer": peer,
+        "direction": direction,
+        "message": redact(message),
+    });
+    let _ = writeln!(transcript.file, "{}", record);
+}
+
+// ============================================================================
+// MCP Client (Generic)
+// ============================================================================
+
+/// Per-invocation options for starting an MCP server
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+struct ClientOptions {
+    /// Append server `notifications/message` entries here as JSON lines
+    log_file: Option<PathBuf>,
+    /// Treat a protocol version mismatch in `initialize` as an error
+    strict_protocol: bool,
+    /// Debug transcript path, forwarded to the daemon
+    debug_transcript: Option<PathBuf>,
+    /// Overrides the profile's `allowed_tools` in the daemon
+    only_tools: Vec<String>,
+    /// Forces the profile's `read_only` on in the daemon
+    read_only: bool,
+    /// Give up on a server that doesn't answer a request within this long
+    response_timeout: Option<Duration>,
+    /// Kill the server when a single response line grows past this many bytes
+    max_response_bytes: Option<usize>,
+    /// Overrides the profile's `ready_probe`
+    wait_ready: Option<Duration>,
+    /// First JSON-RPC id sent to the server (default 1)
+    id_start: Option<u64>,
+    /// Overrides the profile's `daemon_start_timeout_secs` (client side only)
+    #[serde(skip)]
+    start_timeout: Option<Duration>,
+    /// Append to `daemon.log` on start instead of truncating it (client side only)
+    #[serde(skip)]
+    append_log: bool,
+    /// Ephemeral daemon for `call --keep-alive`: allowed without `supports_daemon`,
+    /// and its `idle_timeout` is replaced by this many seconds
+    keep_alive: Option<u64>,
+}
+
+/// Default cap on one server response line, the read-side counterpart of the
+/// daemon's `MAX_REQUEST_SIZE`
+const DEFAULT_MAX_RESPONSE_BYTES: usize = 16 * 1024 * 1024;
+
+/// The only MCP revision whose servers must accept JSON-RPC batches
+const BATCH_PROTOCOL_VERSION: &str = "2025-03-26";
+
+/// How long `call_batch` waits per call for a batch's responses when no
+/// `response_timeout` is set
+const BATCH_RESPONSE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The reply to a request the server sent us (it has both a method and an id), or
+/// `None` for any other message. Only `ping` is supported; anything else, such as
+/// `roots/list`, gets "method not found"
+fn server_request_reply(message: &Value) -> Option<Value> {
+    let method = message.get("method")?.as_str().unwrap_or_default();
+    let id = message.get("id")?;
+    Some(if method == "ping" {
+        json!({"jsonrpc": "2.0", "id": id, "result": {}})
+    } else {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {"code": -32601, "message": format!("Method not found: {}", method)}
+        })
+    })
+}
+
+#[cfg(test)]
+mod server_request_tests {
+    use super::*;
+
+    #[test]
+    fn ping_from_the_server_is_answered() {
+        let reply = server_request_reply(&json!({"jsonrpc": "2.0", "id": "s1", "method": "ping"}));
+        assert_eq!(reply, Some(json!({"jsonrpc": "2.0", "id": "s1", "result": {}})));
+    }
+
+    #[test]
+    fn other_server_requests_get_method_not_found() {
+        let reply = server_request_reply(&json!({"jsonrpc": "2.0", "id": 7, "method": "roots/list"})).unwrap();
+        assert_eq!(reply["id"], 7);
+        assert_eq!(reply["error"]["code"], -32601);
+    }
+
+    #[test]
+    fn responses_and_notifications_are_not_server_requests() {
+        assert_eq!(server_request_reply(&json!({"jsonrpc": "2.0", "id": 1, "result": {}})), None);
+        assert_eq!(server_request_reply(&json!({"jsonrpc": "2.0", "method": "notifications/progress"})), None);
+    }
+}
+
+/// Reads the server's stdout on a background thread so notifications are handled
+/// even while no request is pending; responses are forwarded over the first
+/// channel and `notifications/resources/updated` params over the second.
+/// Requests from the server are answered on `stdin` and never forwarded.
+/// A line longer than `max_bytes` kills the server's process group instead of
+/// being buffered whole
+fn spawn_stdout_reader(
+    stdout: ChildStdout,
+    stdin: Arc<Mutex<ChildStdin>>,
+    server_name: &str,
+    mut log_file: Option<fs::File>,
+    max_bytes: usize,
+    server_pid: u32,
+) -> (Receiver<Result<Value>>, Receiver<Value>) {
+    let (tx, rx) = mpsc::channel();
+    let (updates_tx, updates_rx) = mpsc::channel();
+    let server_name = server_name.to_string();
+
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(stdout);
+        loop {
+            // Read bytes and check the length before decoding, so a cap that splits a
+            // multi-byte character still counts as an oversized line
+            let mut bytes = Vec::new();
+            match (&mut reader).take(max_bytes as u64 + 1).read_until(b'\n', &mut bytes) {
+                Ok(0) => break,
+                Ok(n) if n > max_bytes => {
+                    let _ = kill(Pid::from_raw(-(server_pid as i32)), Signal::SIGKILL);
+                    let _ = tx.send(Err(Failure::Protocol.wrap(anyhow!(
+                        "MCP server response exceeds {} bytes (--max-response-bytes); server stopped",
+                        max_bytes
+                    ))));
+                    break;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    let _ = tx.send(Err(anyhow!("Failed to read from MCP server: {}", e)));
+                    break;
+                }
+            }
+
+            let line = match String::from_utf8(bytes) {
+                Ok(line) => line,
+                Err(e) => {
+                    let err = Failure::Protocol.wrap(anyhow!(e).context("MCP server sent invalid UTF-8"));
+                    if tx.send(Err(err)).is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let message: Value = match serde_json::from_str(line) {
+                Ok(message) => message,
+                Err(e) => {
+                    let err = anyhow!(e).context("Failed to parse JSON-RPC response");
+                    if tx.send(Err(err)).is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            };
+            record_frame("server", "recv", &message);
+
+            // A request from the server is not a response to one of ours
+            if let Some(reply) = server_request_reply(&message) {
+                record_frame("server", "send", &reply);
+                if let Ok(mut stdin) = stdin.lock() {
+                    let _ = writeln!(stdin, "{}", reply).and_then(|()| stdin.flush());
+                }
+                continue;
+            }
+
+            // Notifications carry a method but no id
+            if message.get("method").is_some() && message.get("id").is_none() {
+                if message["method"] == "notifications/message" {
+                    if let Some(file) = log_file.as_mut() {
+                        let params = &message["params"];
+                        let record = json!({
+                            "timestamp": timestamp_rfc3339(),
+                            "server": server_name,
+                            "level": params["level"],
+                            "logger": params["logger"],
+                            "data": params["data"],
+                        });
+                        let _ = writeln!(file, "{}", record);
+                    }
+                } else if message["method"] == "notifications/resources/updated" {
+                    let _ = updates_tx.send(message["params"].clone());
+                }
+                continue;
+            }
+
+            if tx.send(Ok(message)).is_err() {
+                break;
+            }
+        }
+    });
+
+    (rx, updates_rx)
+}
+
+/// A JSON-RPC error response, kept structured so `--error-format json` can
+/// report its code and data
+#[derive(Debug)]
+struct RpcError {
+    /// The `error` object: code, message and optional data
+    error: Value,
+    /// Human-readable text shown by default
+    text: String,
+}
+
+impl RpcError {
+    fn new(error: &Value) -> Self {
+        Self {
+            error: error.clone(),
+            text: format!("MCP Error: {}", error),
+        }
+    }
+
+    fn code(&self) -> Option<i64> {
+        self.error["code"].as_i64()
+    }
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+/// Recursively merges `overlay` into `base`: objects are merged key by key,
+/// anything else in `overlay` replaces the value in `base`
+fn merge_json(base: &mut Value, overlay: &Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                merge_json(base.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+        (base, overlay) => *base = overlay.clone(),
+    }
+}
+
+/// The `result` of a response without `error`; a response with neither is a
+/// protocol error quoting the raw response
+fn take_result(mut response: Value) -> Result<Value> {
+    match response.as_object_mut().and_then(|r| r.remove("result")) {
+        Some(result) => Ok(result),
+        None => Err(Failure::Protocol.wrap(anyhow!(
+            "Invalid JSON-RPC response (no result or error): {}",
+            response
+        ))),
+    }
+}
+
+/// Compares JSON-RPC ids as values, tolerating servers that echo a numeric id
+/// back as a string (and vice versa)
+fn ids_match(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::String(s), Value::Number(n)) | (Value::Number(n), Value::String(s)) => {
+            *s == n.to_string()
+        }
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod ids_match_tests {
+    use super::*;
+
+    #[test]
+    fn numeric_id_echoed_as_string_matches() {
+        let request = json!({"jsonrpc": "2.0", "id": 7, "method": "tools/list"});
+        let response = json!({"jsonrpc": "2.0", "id": "7", "result": {}});
+        assert!(ids_match(&request["id"], &response["id"]));
+        assert!(ids_match(&response["id"], &request["id"]));
+    }
+
+    #[test]
+    fn different_ids_do_not_match() {
+        assert!(!ids_match(&json!(7), &json!("8")));
+        assert!(!ids_match(&json!(7), &json!(8)));
+        assert!(!ids_match(&json!("7a"), &json!(7)));
+        assert!(!ids_match(&json!(null), &json!(7)));
+    }
+}
+
+struct McpClient {
+    child: Child,
+    /// Shared with the stdout reader, which answers the server's own requests
+    stdin: Arc<Mutex<ChildStdin>>,
+    responses: Receiver<Result<Value>>,
+    /// `notifications/resources/updated` params for subscribed resources
+    resource_updates: Receiver<Value>,
+    request_id: u64,
+    /// Result of the initialize handshake (serverInfo, capabilities, ...)
+    init_result: Value,
+    /// Protocol version the server answered with in `initialize`
+    protocol_version: String,
+    response_timeout: Option<Duration>,
+    /// On a response timeout, abandon the request but keep the server (daemon with
+    /// `restart_after_timeouts`) instead of killing it
+    keep_on_timeout: bool,
+    /// Response timeouts since the last answered request
+    consecutive_timeouts: u32,
+    /// Last lines the server wrote to stderr, for crash reports
+    stderr_tail: Arc<Mutex<std::collections::VecDeque<String>>>,
+    /// Container to stop on drop, for container profiles
+    container: Option<RunningContainer>,
+}
+
+/// Lines of server stderr kept for `closed_error`
+const STDERR_TAIL_LINES: usize = 10;
+
+/// Where server stderr goes instead of ours while set, e.g. while the TUI owns the terminal
+static STDERR_TEE_REDIRECT: Mutex<Option<fs::File>> = Mutex::new(None);
+
+/// Copies the server's stderr to ours (or `STDERR_TEE_REDIRECT`) line by line,
+/// keeping the last few lines
+fn spawn_stderr_tee(stderr: ChildStderr) -> Arc<Mutex<std::collections::VecDeque<String>>> {
+    let tail = Arc::new(Mutex::new(std::collections::VecDeque::new()));
+    let thread_tail = Arc::clone(&tail);
+
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(stderr);
+        let mut line = Vec::new();
+        while matches!(reader.read_until(b'\n', &mut line), Ok(n) if n > 0) {
+            match STDERR_TEE_REDIRECT.lock().unwrap().as_mut() {
+                Some(file) => {
+                    let _ = file.write_all(&line);
+                }
+                None => {
+                    let _ = std::io::stderr().write_all(&line);
+                }
+            }
+            let text = String::from_utf8_lossy(&line).trim_end().to_string();
+            let mut tail = thread_tail.lock().unwrap();
+            if tail.len() == STDERR_TAIL_LINES {
+                tail.pop_front();
+            }
+            tail.push_back(text);
+            line.clear();
+        }
+    });
+    tail
+}
+
+/// A started container: its runtime and the `--cidfile` the runtime writes its id to
+struct RunningContainer {
+    runtime: String,
+    cidfile: PathBuf,
+}
+
+impl RunningContainer {
+    /// Stops the container; killing the `run` client alone leaves it running
+    fn stop(&self) {
+        self.signal("stop");
+    }
+
+    /// Like `stop`, without the runtime's grace period
+    fn kill(&self) {
+        self.signal("kill");
+    }
+
+    fn signal(&self, action: &str) {
+        if let Ok(id) = fs::read_to_string(&self.cidfile) {
+            let id = id.trim();
+            if !id.is_empty() {
+                let _ = Command::new(&self.runtime)
+                    .args([action, id])
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status();
+            }
+        }
+        fs::remove_file(&self.cidfile).ok();
+    }
+}
+
+/// Builds `<runtime> run -i --rm --cidfile ...` for a container profile. Profile env
+/// vars are forwarded by name (`-e KEY`), so their values stay off the command line.
+fn container_command(
+    spec: &ContainerSpec,
+    profile: &ServerProfile,
+    server_name: &str,
+    cidfile: &Path,
+) -> Command {
+    let cwd = server_working_dir(profile, server_name);
+    let mut cmd = Command::new(&spec.runtime);
+    cmd.args(["run", "-i", "--rm", "--cidfile"]);
+    cmd.arg(cidfile);
+    for volume in &spec.volumes {
+        cmd.arg("-v");
+        cmd.arg(expand_template_vars(volume, server_name, &cwd));
+    }
+    let passthrough = profile.env_passthrough.iter().filter(|name| std::env::var_os(name).is_some());
+    for name in passthrough.chain(profile.env.keys()) {
+        cmd.arg("-e");
+        cmd.arg(name);
+    }
+    cmd.args(spec.run_args.iter().map(|arg| expand_template_vars(arg, server_name, &cwd)));
+    cmd.arg(&spec.image);
+    cmd.args(&profile.command);
+    cmd
+}
+
+/// Process groups of the running MCP servers (several during `index`), killed on SIGINT/SIGTERM
+static SERVER_PGIDS: Mutex<BTreeSet<i32>> = Mutex::new(BTreeSet::new());
+
+extern "C" fn kill_server_and_exit(signo: c_int) {
+    // Only async-signal-safe calls here: kill, signal, raise. The set is held only
+    // briefly to add or remove a group, so try_lock rather than risk a deadlock
+    if let Ok(pgids) = SERVER_PGIDS.try_lock() {
+        for &pgid in pgids.iter() {
+            let _ = kill(Pid::from_raw(-pgid), Signal::SIGKILL);
+        }
+    }
+    if let Ok(sig) = Signal::try_from(signo) {
+        // Re-raise with the default action so the exit status reflects the signal
+        unsafe {
+            let _ = signal(sig, SigHandler::SigDfl);
+        }
+        let _ = raise(sig);
+    }
+}
+
+/// Kills the server's process group if this process is interrupted, since
+/// `Drop` does not run when a signal terminates the process
+fn kill_server_on_interrupt(pgid: u32) {
+    static INSTALL: Once = Once::new();
+
+    SERVER_PGIDS.lock().unwrap().insert(pgid as i32);
+    INSTALL.call_once(|| {
+        for sig in [Signal::SIGINT, Signal::SIGTERM] {
+            unsafe {
+                let _ = signal(sig, SigHandler::Handler(kill_server_and_exit));
+            }
+        }
+    });
+}
+
+/// Set by SIGINT once `stop_on_interrupt` replaces the default cleanup handler
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn set_interrupted(_: c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Makes SIGINT only set `INTERRUPTED`, for loops that clean up before exiting
+fn stop_on_interrupt() {
+    unsafe {
+        let _ = signal(Signal::SIGINT, SigHandler::Handler(set_interrupted));
+    }
+}
+
+impl McpClient {
+    fn start(
+        profile: &ServerProfile,
+        extra_args: Option<Vec<String>>,
+        server_name: &str,
+        options: &ClientOptions,
+    ) -> Result<Self> {
+        eprintln!("🚀 Starting MCP server...");
+
+        let cwd = server_working_dir(profile, server_name);
+        if !cwd.is_dir() {
+            return Err(Failure::Config.wrap(anyhow!("Working directory does not exist: {}", cwd.display())));
+        }
+
+        let mut container = None;
+        let mut cmd = if let Some(spec) = &profile.container {
+            // docker refuses to overwrite a cidfile, so each launch gets a fresh one
+            static LAUNCHES: AtomicU32 = AtomicU32::new(0);
+            let cidfile = std::env::temp_dir().join(format!(
+                "mcp-valve-{}-{}.cid",
+                std::process::id(),
+                LAUNCHES.fetch_add(1, Ordering::SeqCst)
+            ));
+            fs::remove_file(&cidfile).ok();
+            let cmd = container_command(spec, profile, server_name, &cidfile);
+            container = Some(RunningContainer {
+                runtime: spec.runtime.clone(),
+                cidfile,
+            });
+            cmd
+        } else {
+            if profile.command.is_empty() {
+                return Err(anyhow!("Server profile has empty command"));
+            }
+
+            let mut cmd = Command::new(&profile.command[0]);
+
+            // Add command args (e.g., for npx: "@playwright/mcp@latest")
+            if profile.command.len() > 1 {
+                cmd.args(&profile.command[1..]);
+            }
+            cmd
+        };
+
+        // Add args: if --server-args was provided (even if empty), use it to override default_args
+        // Otherwise use default_args from profile
+        // Template variables are expanded for both default_args and extra_args
+        let args_to_use = match extra_args {
+            Some(args) => args.iter().map(|arg| expand_template_vars(arg, server_name, &cwd)).collect(),
+            None => profile.default_args.iter().map(|arg| expand_template_vars(arg, server_name, &cwd)).collect::<Vec<String>>(),
+        };
+        cmd.args(&args_to_use);
+        cmd.current_dir(&cwd);
+
+        apply_profile_env(&mut cmd, profile);
+
+        // Own process group, so the server and anything it spawns die together
+        let mut child = cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0)
+            .spawn()
+            .with_context(|| match &profile.container {
+                Some(spec) => format!("Failed to start container {} with {}", spec.image, spec.runtime),
+                None => format!("Failed to spawn MCP server: {:?}", profile.command),
+            })
+            .map_err(|e| Failure::Unavailable.wrap(e))?;
+        kill_server_on_interrupt(child.id());
+
+        let log_file = options.log_file.as_ref().map(open_append).transpose()?;
+
+        let stdin = Arc::new(Mutex::new(child.stdin.take().unwrap()));
+        let stderr_tail = spawn_stderr_tee(child.stderr.take().unwrap());
+        let (responses, resource_updates) = spawn_stdout_reader(
+            child.stdout.take().unwrap(),
+            Arc::clone(&stdin),
+            server_name,
+            log_file,
+            options.max_response_bytes.unwrap_or(DEFAULT_MAX_RESPONSE_BYTES),
+            child.id(),
+        );
+
+        let mut mcp = Self {
+            child,
+            stdin,
+            responses,
+            resource_updates,
+            request_id: options.id_start.unwrap_or(1).saturating_sub(1),
+            init_result: Value::Null,
+            protocol_version: String::new(),
+            response_timeout: options.response_timeout.or(profile.request_timeout.map(Duration::from_secs)),
+            keep_on_timeout: false,
+            consecutive_timeouts: 0,
+            stderr_tail,
+            container,
+        };
+
+        mcp.initialize(&profile.initialize_params, options.strict_protocol)?;
+        mcp.check_capabilities(&profile.require_capabilities)?;
+        if let Some(timeout) = options.wait_ready.or(profile.ready_probe.map(Duration::from_secs)) {
+            mcp.wait_ready(timeout)?;
+        }
+        eprintln!("✅ MCP server ready");
+        Ok(mcp)
+    }
+
+    /// Fails if the server did not declare every capability in `required`. Dotted
+    /// names reach into a capability, e.g. `resources.subscribe`.
+    fn check_capabilities(&self, required: &[String]) -> Result<()> {
+        let capabilities = &self.init_result["capabilities"];
+        let missing: Vec<&str> = required
+            .iter()
+            .map(String::as_str)
+            .filter(|name| {
+                let value = name.split('.').try_fold(capabilities, |value, key| value.get(key));
+                matches!(value, None | Some(Value::Null) | Some(Value::Bool(false)))
+            })
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        Err(Failure::Protocol.wrap(anyhow!(
+            "MCP server is missing required capabilities: {} (declared: {})",
+            missing.join(", "),
+            capabilities
+        )))
+    }
+
+    /// Retries `tools/list` with backoff until it succeeds. Some servers answer
+    /// `initialize` before they can serve tools.
+    fn wait_ready(&mut self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut delay = Duration::from_millis(100);
+        loop {
+            let err = match self.list_tools() {
+                Ok(_) => return Ok(()),
+                Err(e) => e,
+            };
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(Failure::Timeout.wrap(
+                    err.context(format!("MCP server not ready after {}s", timeout.as_secs_f64())),
+                ));
+            }
+            std::thread::sleep(delay.min(remaining));
+            delay = (delay * 2).min(Duration::from_secs(2));
+        }
+    }
+
+    fn initialize(&mut self, extra_params: &serde_json::Map<String, Value>, strict_protocol: bool) -> Result<()> {
+        let mut init_request = json!({
+            "jsonrpc": "2.0",
+            "id": self.next_id(),
+            "method": "initialize",
+            "params": {
+                "protocolVersion": "2025-06-18",
+                "capabilities": {},
+                "clientInfo": {
+                    "name": "mcp-valve",
+                    "version": env!("CARGO_PKG_VERSION")
+                }
+            }
+        });
+        for key in ["protocolVersion", "clientInfo"] {
+            if extra_params.contains_key(key) {
+                eprintln!("Note: initialize_params overrides '{}'", key);
+            }
+        }
+        merge_json(&mut init_request["params"], &Value::Object(extra_params.clone()));
+
+        let response = self.send_request(&init_request)?;
+        self.init_result = take_result(response)?;
+
+        let requested = init_request["params"]["protocolVersion"].as_str().unwrap_or_default();
+        let negotiated = self.init_result["protocolVersion"].as_str().unwrap_or_default();
+        if negotiated != requested {
+            let message = format!(
+                "Server answered with protocol version {} (requested {})",
+                if negotiated.is_empty() { "<none>" } else { negotiated },
+                requested
+            );
+            if strict_protocol {
+                return Err(Failure::Protocol.wrap(anyhow!(message)));
+            }
+            eprintln!("{} {}", stderr_label("Warning:", COLOR_WARNING), message);
+        }
+        self.protocol_version = negotiated.to_string();
+
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/initialized",
+            "params": {}
+        });
+
+        self.send_notification(&notification)?;
+        Ok(())
+    }
+
+    /// serverInfo reported by the server during initialize
+    fn server_info(&self) -> Option<&Value> {
+        self.init_result.get("serverInfo")
+    }
+
+    fn write_message(&mut self, message: &Value) -> Result<()> {
+        record_frame("server", "send", message);
+        let message_str = serde_json::to_string(message)?;
+        let written = {
+            let mut stdin = self.stdin.lock().unwrap();
+            writeln!(stdin, "{}", message_str).and_then(|()| stdin.flush())
+        };
+        match written {
+            Ok(()) => Ok(()),
+            // The server is gone; report how it ended rather than "Broken pipe"
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Err(self.closed_error()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// PID of the server process, which leads its own process group
+    fn server_pid(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// The server's exit status, once it has exited
+    fn exit_status(&mut self) -> Option<std::process::ExitStatus> {
+        self.child.try_wait().ok().flatten()
+    }
+
+    /// Error for a server that closed its stdout: its exit status (waiting
+    /// briefly, as the close usually comes just before the exit) and the last
+    /// lines it wrote to stderr
+    fn closed_error(&mut self) -> anyhow::Error {
+        let deadline = Instant::now() + Duration::from_millis(500);
+        let status = loop {
+            match self.exit_status() {
+                Some(status) => break format!("exited with {}", status),
+                None if Instant::now() >= deadline => break "still running".to_string(),
+                None => std::thread::sleep(Duration::from_millis(20)),
+            }
+        };
+
+        let mut message = format!("MCP server closed the connection unexpectedly ({})", status);
+        let tail = self.stderr_tail.lock().unwrap();
+        if !tail.is_empty() {
+            message.push_str("\nLast stderr lines:");
+            for line in tail.iter() {
+                message.push_str("\n  ");
+                message.push_str(line);
+            }
+        }
+        Failure::Protocol.wrap(anyhow!(message))
+    }
+
+    fn read_response(&mut self) -> Result<Value> {
+        let Some(timeout) = self.response_timeout else {
+            return match self.responses.recv() {
+                Ok(response) => response,
+                Err(_) => Err(self.closed_error()),
+            };
+        };
+        match self.responses.recv_timeout(timeout) {
+            Ok(response) => response,
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(Failure::Timeout.wrap(anyhow!(
+                "MCP server did not respond within {}s",
+                timeout.as_secs_f64()
+            ))),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(self.closed_error()),
+        }
+    }
+
+    /// Reads the response to `request`. A server that lets the response timeout
+    /// pass is killed, as it can't be trusted to answer the next request either,
+    /// unless `keep_on_timeout` is set; then the request is abandoned and counted
+    fn read_response_to(&mut self, request: &Value) -> Result<Value> {
+        match self.read_response() {
+            Ok(response) => {
+                // A late answer to an abandoned request doesn't mean the server recovered
+                if response.is_array() || ids_match(&response["id"], &request["id"]) {
+                    self.consecutive_timeouts = 0;
+                }
+                return Ok(response);
+            }
+            Err(e) if Failure::of(&e) != Some(Failure::Timeout) => return Err(e),
+            Err(_) => {}
+        }
+        self.consecutive_timeouts += 1;
+        let what = match request {
+            Value::Array(batch) => format!("a batch of {} tool calls", batch.len()),
+            request if request["method"] == "tools/call" => {
+                format!("tools/call '{}'", request["params"]["name"].as_str().unwrap_or("?"))
+            }
+            request => request["method"].as_str().unwrap_or("?").to_string(),
+        };
+        let secs = self.response_timeout.unwrap_or_default().as_secs_f64();
+        if self.keep_on_timeout {
+            eprintln!(
+                "[{}] No answer to {} within {}s, request abandoned ({} in a row)",
+                timestamp_rfc3339(),
+                what,
+                secs,
+                self.consecutive_timeouts
+            );
+            return Err(Failure::Timeout.wrap(anyhow!(
+                "MCP server did not answer {} within {}s; request abandoned",
+                what,
+                secs
+            )));
+        }
+        kill(Pid::from_raw(-(self.child.id() as i32)), Signal::SIGKILL).ok();
+        Err(Failure::Timeout.wrap(anyhow!(
+            "MCP server did not answer {} within {}s; server stopped",
+            what,
+            secs
+        )))
+    }
+
+    /// Sends a request and returns the server's response object as-is, error included
+    fn exchange(&mut self, request: &Value) -> Result<Value> {
+        self.write_message(request)?;
+
+        // Skip responses to other (e.g. abandoned) requests; an error with a null id
+        // means the server couldn't parse ours, so it is ours too
+        let response = loop {
+            let response = self.read_response_to(request)?;
+            if ids_match(&response["id"], &request["id"])
+                || (response["id"].is_null() && response.get("error").is_some())
+            {
+                break response;
+            }
+            eprintln!("Ignoring response with unexpected id {}", response["id"]);
+        };
+        Ok(response)
+    }
+
+    fn send_request(&mut self, request: &Value) -> Result<Value> {
+        let response = self.exchange(request)?;
+        if let Some(error) = response.get("error") {
+            return Err(RpcError::new(error).into());
+        }
+
+        Ok(response)
+    }
+
+    fn send_notification(&mut self, notification: &Value) -> Result<()> {
+        self.write_message(notification)
+    }
+
+    fn next_id(&mut self) -> u64 {
+        self.request_id += 1;
+        self.request_id
+    }
+
+    fn call_tool(&mut self, name: &str, args: Value) -> Result<Value> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": self.next_id(),
+            "method": "tools/call",
+            "params": {
+                "name": name,
+                "arguments": args
+            }
+        });
+
+        let response = self.send_request(&request);
+        self.tool_result(name, response)
+    }
+
+    /// Reads the reply to `batch`, skipping single responses to other (e.g. abandoned)
+    /// requests before deciding the batch was rejected, as `exchange` does
+    fn read_batch_response(&mut self, batch: &Value, requests: &[Value]) -> Result<Value> {
+        loop {
+            let response = self.read_response_to(batch)?;
+            if response.is_array()
+                || response["id"].is_null()
+                || requests.iter().any(|r| ids_match(&response["id"], &r["id"]))
+            {
+                return Ok(response);
+            }
+            eprintln!("Ignoring response with unexpected id {}", response["id"]);
+        }
+    }
+
+    /// Whether the server takes JSON-RPC batches: only protocol revision 2025-03-26
+    /// requires them (2025-06-18 removed batching again)
+    fn supports_batch(&self) -> bool {
+        self.protocol_version == BATCH_PROTOCOL_VERSION
+    }
+
+    fn call_each(&mut self, calls: &[(String, Value)]) -> Vec<Result<Value>> {
+        calls.iter().map(|(name, args)| self.call_tool(name, args.clone())).collect()
+    }
+
+    /// Sends several `tools/call` requests as one JSON-RPC batch and correlates the
+    /// responses back to each call by id. Calls go one by one instead when the
+    /// server's protocol version has no batching, or when it rejects the batch as a
+    /// whole (a single error instead of an array). Without a `response_timeout`,
+    /// `BATCH_RESPONSE_TIMEOUT` per call bounds the wait, so a server that ignores
+    /// arrays can't block forever; that timeout abandons the batch but keeps the server.
+    fn call_batch(&mut self, calls: &[(String, Value)]) -> Result<Vec<Result<Value>>> {
+        if !self.supports_batch() {
+            return Ok(self.call_each(calls));
+        }
+
+        let requests: Vec<Value> = calls
+            .iter()
+            .map(|(name, args)| {
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": self.next_id(),
+                    "method": "tools/call",
+                    "params": {
+                        "name": name,
+                        "arguments": args
+                    }
+                })
+            })
+            .collect();
+
+        let batch = Value::Array(requests.clone());
+        self.write_message(&batch)?;
+        let configured = (self.response_timeout, self.keep_on_timeout);
+        if self.response_timeout.is_none() {
+            self.response_timeout = Some(BATCH_RESPONSE_TIMEOUT * calls.len() as u32);
+            self.keep_on_timeout = true;
+        }
+        let response = self.read_batch_response(&batch, &requests);
+        (self.response_timeout, self.keep_on_timeout) = configured;
+        let response = response?;
+
+        let Some(responses) = response.as_array() else {
+            eprintln!("Server rejected JSON-RPC batch, falling back to sequential calls");
+            return Ok(self.call_each(calls));
+        };
+
+        Ok(requests
+            .iter()
+            .zip(calls)
+            .map(|(request, (name, _))| {
+                let response = match responses.iter().find(|r| ids_match(&r["id"], &request["id"])) {
+                    Some(r) => match r.get("error") {
+                        Some(error) => Err(RpcError::new(error).into()),
+                        None => Ok(r.clone()),
+                    },
+                    None => Err(anyhow!("No response for batched call to '{}'", name)),
+                };
+                self.tool_result(name, response)
+            })
+            .collect())
+    }
+
+    /// Extracts the result of a `tools/call` response, turning protocol errors and
+    /// tool-level errors (`isError`) into errors with the tool schema appended
+    fn tool_result(&mut self, name: &str, response: Result<Value>) -> Result<Value> {
+        let response = match response {
+            Ok(resp) => resp,
+            Err(e) => {
+                let failure = Failure::of(&e);
+                // A server that just timed out would only time out on tools/list too
+                let error_with_schema = match failure {
+                    Some(Failure::Timeout) => e.to_string(),
+                    _ => self.format_error_with_schema(name, &e.to_string()),
+                };
+                return Err(match e.downcast::<RpcError>() {
+                    Ok(rpc) => RpcError { text: error_with_schema, ..rpc }.into(),
+                    Err(_) => match failure {
+                        Some(failure) => failure.wrap(anyhow!("{}", error_with_schema)),
+                        None => anyhow!("{}", error_with_schema),
+                    },
+                });
+            }
+        };
+        let result = take_result(response)?;
+
+        // Check for tool-level errors (isError field in result)
+        if let Some(is_error) = result.get("isError").and_then(|v| v.as_bool()) {
+            if is_error {
+                // Extract error message from content if available
+                let error_msg = result
+                    .get("content")
+                    .and_then(|c| c.as_array())
+                    .and_then(|arr| arr.first())
+                    .and_then(|item| item.get("text"))
+                    .and_then(|t| t.as_str())
+                    .unwrap_or("Tool execution failed");
+
+                let error_with_schema =
+                    self.format_error_with_schema(name, &format!("Tool Error: {}", error_msg));
+                return Err(Failure::Tool.wrap(anyhow!("{}", error_with_schema)));
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Lists every tool, following `nextCursor` across pages
+    fn list_tools(&mut self) -> Result<Value> {
+        let mut tools = Vec::new();
+        let mut params = json!({});
+        loop {
+            let mut result = self.request("tools/list", params.clone())?;
+            if let Some(page) = result.get_mut("tools").and_then(Value::as_array_mut) {
+                tools.append(page);
+            }
+            let cursor = result.as_object_mut().and_then(|r| r.remove("nextCursor"));
+            match cursor.filter(|c| !c.is_null()) {
+                Some(cursor) if cursor == params["cursor"] => {
+                    return Err(Failure::Protocol.wrap(anyhow!("tools/list returned the same nextCursor twice: {}", cursor)));
+                }
+                Some(cursor) => params = json!({"cursor": cursor}),
+                None => {
+                    result["tools"] = Value::Array(tools);
+                    return Ok(result);
+                }
+            }
+        }
+    }
+
+    /// Sends any method and returns the raw response, or `null` for a notification
+    fn raw_request(&mut self, method: &str, params: Value, notification: bool) -> Result<Value> {
+        if notification {
+            self.send_notification(&json!({"jsonrpc": "2.0", "method": method, "params": params}))?;
+            return Ok(Value::Null);
+        }
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": self.next_id(),
+            "method": method,
+            "params": params
+        });
+        self.exchange(&request)
+    }
+
+    /// Sends a request and returns its `result`
+    fn request(&mut self, method: &str, params: Value) -> Result<Value> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": self.next_id(),
+            "method": method,
+            "params": params
+        });
+
+        let response = self.send_request(&request)?;
+        take_result(response)
+    }
+
+    fn list_resources(&mut self) -> Result<Value> {
+        self.request("resources/list", json!({}))
+    }
+
+    fn read_resource(&mut self, uri: &str) -> Result<Value> {
+        self.request("resources/read", json!({"uri": uri}))
+    }
+
+    fn list_prompts(&mut self) -> Result<Value> {
+        self.request("prompts/list", json!({}))
+    }
+
+    fn get_prompt(&mut self, name: &str, args: Value) -> Result<Value> {
+        self.request("prompts/get", json!({"name": name, "arguments": args}))
+    }
+
+    fn subscribe(&mut self, uri: &str) -> Result<Value> {
+        self.request("resources/subscribe", json!({"uri": uri}))
+    }
+
+    fn unsubscribe(&mut self, uri: &str) -> Result<Value> {
+        self.request("resources/unsubscribe", json!({"uri": uri}))
+    }
+
+    /// Waits up to `timeout` for the next `notifications/resources/updated` params
+    fn next_resource_update(&mut self, timeout: Duration) -> Result<Option<Value>> {
+        match self.resource_updates.recv_timeout(timeout) {
+            Ok(params) => Ok(Some(params)),
+            Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(self.closed_error()),
+        }
+    }
+
+    /// Asks the server for values completing `partial` for one argument of a
+    /// prompt or resource template (`reference` is a `ref/prompt` or `ref/resource`)
+    fn complete(&mut self, reference: Value, argument_name: &str, partial: &str) -> Result<Value> {
+        self.request(
+            "completion/complete",
+            json!({
+                "ref": reference,
+                "argument": {"name": argument_name, "value": partial}
+            }),
+        )
+    }
+
+    /// Get the inputSchema for a specific tool
+    fn get_tool_schema(&mut self, tool_name: &str) -> Option<Value> {
+        self.list_tools()
+            .ok()
+            .and_then(|result| result.get("tools").cloned())
+            .and_then(|tools| tools.as_array().cloned())
+            .and_then(|tools| {
+                tools
+                    .into_iter()
+                    .find(|t| t.get("name").and_then(|n| n.as_str()) == Some(tool_name))
+            })
+            .and_then(|tool| tool.get("inputSchema").cloned())
+    }
+
+    /// Format error message with tool schema appended
+    fn format_error_with_schema(&mut self, tool_name: &str, error_msg: &str) -> String {
+        match self.get_tool_schema(tool_name) {
+            Some(schema) => {
+                let schema_str = serde_json::to_string_pretty(&schema)
+                    .unwrap_or_else(|_| schema.to_string());
+                format!(
+                    "{}\n\nSchema for tool '{}':\n{}",
+                    error_msg, tool_name, schema_str
+                )
+            }
+            None => error_msg.to_string(),
+        }
+    }
+}
+
+impl Drop for McpClient {
+    fn drop(&mut self) {
+        if let Some(container) = &self.container {
+            container.stop();
+        }
+        let pgid = self.child.id() as i32;
+        SERVER_PGIDS.lock().unwrap().remove(&pgid);
+        let _ = kill(Pid::from_raw(-pgid), Signal::SIGKILL);
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// ============================================================================
+// Project Context
+// ============================================================================
+
+/// Get the current project path (current working directory)
+fn get_project_path() -> String {
+    std::env::current_dir()
+        .ok()
+        .and_then(|p| p.to_str().map(|s| s.to_string()))
+        .unwrap_or_else(|| ".".to_string())
+}
+
+/// Format error message when daemon is not running
+fn daemon_not_running_error(server_name: &str) -> anyhow::Error {
+    let project = get_project_path();
+    let instance = daemon_instance()
+        .map(|i| format!(" --instance {}", i))
+        .unwrap_or_default();
+    Failure::Unavailable.wrap(anyhow!(
+        "Daemon is not running for project '{}'\n\n\
+        Start daemon with:\n  \
+        cd {}\n  \
+        mcp-valve --server {}{} start-daemon",
+        project, project, server_name, instance
+    ))
+}
+
+// ============================================================================
+// Daemon Management
+// ============================================================================
+
+/// Daemon metadata persisted to `daemon.json` next to the PID file
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct DaemonState {
+    pid: u32,
+    /// Process start time in clock ticks since boot (Linux only), used to detect PID reuse
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    start_time: Option<u64>,
+    /// PID of the MCP server the daemon is currently running
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    server_pid: Option<u32>,
+    /// What the daemon was started with, replayed by `restart-daemon`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    launch: Option<DaemonLaunch>,
+}
+
+/// Everything `start` passes to the `__internal_daemon` process. The daemon
+/// records its own copy in `daemon.json`, so a restart can start it the same way
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+struct DaemonLaunch {
+    config: ConfigSelection,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    server_args: Option<Vec<String>>,
+    /// `--env` overrides, already expanded
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    env: HashMap<String, String>,
+    #[serde(default)]
+    options: ClientOptions,
+}
+
+impl DaemonLaunch {
+    /// Arguments after `__internal_daemon --server <name>`
+    fn daemon_args(&self) -> Result<Vec<String>> {
+        let mut args = Vec::new();
+        // The daemon must load the same config files that resolved this server
+        match self.config.absolute() {
+            ConfigSelection::File(path) => {
+                args.push("--config".to_string());
+                args.push(path.display().to_string());
+            }
+            ConfigSelection::Layers(layers) => {
+                args.push("--config-layers".to_string());
+                args.push(serde_json::to_string(&layers)?);
+            }
+        }
+
+        if let Some(ref server_args) = self.server_args {
+            args.push("--server-args".to_string());
+            args.push(serde_json::to_string(server_args)?);
+        }
+
+        let mut env: Vec<_> = self.env.iter().collect();
+        env.sort();
+        for (key, value) in env {
+            args.push("--env".to_string());
+            args.push(format!("{}={}", key, value));
+        }
+
+        let options = &self.options;
+        if let Some(ref log_file) = options.log_file {
+            args.push("--log-file".to_string());
+            args.push(log_file.display().to_string());
+        }
+
+        if options.strict_protocol {
+            args.push("--strict-protocol".to_string());
+        }
+
+        if let Some(ref path) = options.debug_transcript {
+            args.push("--debug-transcript".to_string());
+            args.push(path.display().to_string());
+        }
+
+        for tool in &options.only_tools {
+            args.push("--only-tool".to_string());
+            args.push(tool.clone());
+        }
+
+        if options.read_only {
+            args.push("--read-only".to_string());
+        }
+
+        if let Some(max) = options.max_response_bytes {
+            args.push("--max-response-bytes".to_string());
+            args.push(max.to_string());
+        }
+
+        if let Some(timeout) = options.wait_ready {
+            args.push("--wait-ready".to_string());
+            args.push(timeout.as_secs_f64().to_string());
+        }
+
+        if let Some(start) = options.id_start {
+            args.push("--json-rpc-id-start".to_string());
+            args.push(start.to_string());
+        }
+
+        if let Some(timeout) = options.response_timeout {
+            args.push("--request-timeout".to_string());
+            args.push(timeout.as_secs_f64().to_string());
+        }
+
+        if let Some(idle) = options.keep_alive {
+            args.push("--keep-alive".to_string());
+            args.push(idle.to_string());
+        }
+        Ok(args)
+    }
+
+    /// Parses `daemon_args` back, by hand since the daemon runs before clap
+    fn from_daemon_args(args: &[String]) -> Result<Self> {
+        let value = |flag: &str| args.iter()
+            .position(|a| a == flag)
+            .and_then(|i| args.get(i + 1));
+        let values = |flag: &str| -> Vec<String> {
+            args.windows(2)
+                .filter(|w| w[0] == flag)
+                .map(|w| w[1].clone())
+                .collect()
+        };
+
+        let config = match value("--config-layers").and_then(|s| serde_json::from_str(s).ok()) {
+            Some(layers) => ConfigSelection::Layers(layers),
+            None => ConfigSelection::resolve(value("--config").or_else(|| value("-c")).map(PathBuf::from))?,
+        };
+
+        Ok(Self {
+            config,
+            server_args: value("--server-args").and_then(|s| serde_json::from_str(s).ok()),
+            env: split_env_pairs(&values("--env"))?,
+            options: ClientOptions {
+                log_file: value("--log-file").map(PathBuf::from),
+                strict_protocol: args.iter().any(|a| a == "--strict-protocol"),
+                debug_transcript: value("--debug-transcript").map(PathBuf::from),
+                only_tools: values("--only-tool"),
+                read_only: args.iter().any(|a| a == "--read-only"),
+                response_timeout: value("--request-timeout").and_then(|v| parse_seconds(v).ok()),
+                max_response_bytes: value("--max-response-bytes").and_then(|v| v.parse().ok()),
+                wait_ready: value("--wait-ready").and_then(|v| parse_seconds(v).ok()),
+                id_start: value("--json-rpc-id-start").and_then(|v| v.parse().ok()),
+                start_timeout: None,
+                append_log: false,
+                keep_alive: value("--keep-alive").and_then(|v| v.parse().ok()),
+            },
+        })
+    }
+
+    /// A restart's launch: server args, env overrides and options given to
+    /// `restart-daemon` win, everything else is what the daemon was started with
+    fn restarted(self, server_args: Option<Vec<String>>, env: HashMap<String, String>, options: &ClientOptions) -> Self {
+        let recorded = self.options;
+        let mut merged_env = self.env;
+        merged_env.extend(env);
+        Self {
+            config: self.config,
+            server_args: server_args.or(self.server_args),
+            env: merged_env,
+            options: ClientOptions {
+                log_file: options.log_file.clone().or(recorded.log_file),
+                strict_protocol: options.strict_protocol || recorded.strict_protocol,
+                debug_transcript: options.debug_transcript.clone().or(recorded.debug_transcript),
+                only_tools: if options.only_tools.is_empty() { recorded.only_tools } else { options.only_tools.clone() },
+                read_only: options.read_only || recorded.read_only,
+                response_timeout: options.response_timeout.or(recorded.response_timeout),
+                max_response_bytes: options.max_response_bytes.or(recorded.max_response_bytes),
+                wait_ready: options.wait_ready.or(recorded.wait_ready),
+                id_start: options.id_start.or(recorded.id_start),
+                start_timeout: options.start_timeout,
+                append_log: options.append_log,
+                keep_alive: options.keep_alive.or(recorded.keep_alive),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod daemon_launch_tests {
+    use super::*;
+
+    fn custom_launch() -> DaemonLaunch {
+        DaemonLaunch {
+            config: ConfigSelection::Layers(vec![PathBuf::from("/etc/team.json"), PathBuf::from("/home/me/mine.json")]),
+            server_args: Some(vec!["--port".to_string(), "9000".to_string()]),
+            env: HashMap::from([("TOKEN".to_string(), "a${HOME}b".to_string())]),
+            options: ClientOptions {
+                only_tools: vec!["search".to_string()],
+                read_only: true,
+                response_timeout: Some(Duration::from_secs(30)),
+                wait_ready: Some(Duration::from_secs(5)),
+                max_response_bytes: Some(1024),
+                id_start: Some(100),
+                strict_protocol: true,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn daemon_args_parse_back_to_the_same_launch() {
+        let launch = custom_launch();
+        let args = launch.daemon_args().unwrap();
+        assert_eq!(DaemonLaunch::from_daemon_args(&args).unwrap(), launch);
+    }
+
+    #[test]
+    fn restart_replays_the_recorded_launch() {
+        let dir = std::env::temp_dir().join(format!("mcp-valve-test-{}-restart", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let manager = DaemonManager::at("test", &dir);
+        let launch = custom_launch();
+        manager.write_state(&DaemonState { pid: 1, launch: Some(launch.clone()), ..Default::default() }).unwrap();
+        let recorded = manager.read_state().and_then(|state| state.launch);
+        fs::remove_dir_all(&dir).ok();
+
+        let restarted = recorded.unwrap().restarted(None, HashMap::new(), &ClientOptions::default());
+        assert_eq!(restarted.daemon_args().unwrap(), launch.daemon_args().unwrap());
+    }
+
+    #[test]
+    fn restart_options_win_over_recorded_ones() {
+        let options = ClientOptions { response_timeout: Some(Duration::from_secs(5)), ..Default::default() };
+        let env = HashMap::from([("TOKEN".to_string(), "new".to_string())]);
+        let restarted = custom_launch().restarted(Some(vec!["--verbose".to_string()]), env, &options);
+        assert_eq!(restarted.server_args, Some(vec!["--verbose".to_string()]));
+        assert_eq!(restarted.env["TOKEN"], "new");
+        assert_eq!(restarted.options.response_timeout, Some(Duration::from_secs(5)));
+        assert!(restarted.options.read_only);
+    }
+}
+
+/// Activity counters the daemon keeps in `stats.json`
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct DaemonStats {
+    /// Unix time the daemon started accepting clients
+    started_at: u64,
+    /// Client requests handled
+    requests: u64,
+    /// Unix time of the last handled request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_activity: Option<u64>,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// Formats seconds as e.g. `45s`, `3m05s` or `2h03m`
+fn format_duration(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Read a process start time from /proc/<pid>/stat (Linux only)
+fn process_start_time(pid: i32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // comm (field 2) may contain spaces, so parse from the last ')'; starttime is field 22
+    let rest = &stat[stat.rfind(')')? + 1..];
+    rest.split_whitespace().nth(19)?.parse().ok()
+}
+
+struct DaemonManager {
+    server_name: String,
+    pid_file: PathBuf,
+    state_file: PathBuf,
+    stats_file: PathBuf,
+}
+
+impl DaemonManager {
+    fn new(server_name: &str) -> Self {
+        let profile_dir = server_profile_dir(server_name);
+
+        // Ensure profile directory exists with secure permissions (0700)
+        if !profile_dir.exists() {
+            let old_umask = umask(Mode::from_bits_truncate(0o077));
+            fs::create_dir_all(&profile_dir)
+                .expect("Failed to create daemon profile directory");
+            umask(old_umask);
+        }
+
+        Self::at(server_name, &profile_dir)
+    }
+
+    /// The daemon whose files live in `profile_dir`, which is left untouched
+    fn at(server_name: &str, profile_dir: &Path) -> Self {
+        Self {
+            server_name: server_name.to_string(),
+            pid_file: profile_dir.join("daemon.pid"),
+            state_file: profile_dir.join("daemon.json"),
+            stats_file: profile_dir.join("stats.json"),
+        }
+    }
+
+    /// `server` or `server@instance`: the profile dir's name, which also names the socket
+    fn label(&self) -> String {
+        self.pid_file.parent()
+            .and_then(Path::file_name)
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| daemon_label(&self.server_name))
+    }
+
+    fn log_file(&self) -> PathBuf {
+        self.pid_file.with_file_name("daemon.log")
+    }
+
+    /// `stopping` while the daemon shuts its server down and runs `post_stop`,
+    /// then `ok` or `failed: <error>`; read by `stop`
+    fn shutdown_file(&self) -> PathBuf {
+        self.pid_file.with_file_name("shutdown")
+    }
+
+    fn read_stats(&self) -> Option<DaemonStats> {
+        let content = fs::read_to_string(&self.stats_file).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    fn write_stats(&self, stats: &DaemonStats) -> Result<()> {
+        fs::write(&self.stats_file, serde_json::to_string(stats)?)
+            .context("Failed to write daemon stats file")
+    }
+
+    /// One-line summary of uptime and activity, if the daemon has written stats
+    fn stats_summary(&self) -> Option<String> {
+        let stats = self.read_stats()?;
+        let now = unix_now();
+        let last = match stats.last_activity {
+            Some(at) => format!("{} ago", format_duration(now.saturating_sub(at))),
+            None => "never".to_string(),
+        };
+        Some(format!(
+            "Uptime: {}  Requests: {}  Last activity: {}",
+            format_duration(now.saturating_sub(stats.started_at)),
+            stats.requests,
+            last
+        ))
+    }
+
+    fn read_state(&self) -> Option<DaemonState> {
+        let content = fs::read_to_string(&self.state_file).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    fn write_state(&self, state: &DaemonState) -> Result<()> {
+        fs::write(&self.state_file, serde_json::to_string_pretty(state)?)
+            .context("Failed to write daemon state file")
+    }
+
+    /// Records the PID of the server this daemon process just started. Only the
+    /// daemon writes `daemon.json`, from its own copy of the state
+    fn record_server_pid(&self, state: &mut DaemonState, server_pid: u32) {
+        state.server_pid = Some(server_pid);
+        self.write_state(state).ok();
+    }
+
+    /// Check that a live PID is really our daemon and not an unrelated process
+    /// that reused the PID (e.g. after a reboot)
+    fn owns_pid(&self, pid: i32) -> bool {
+        if let Some(state) = self.read_state() {
+            if state.pid as i32 == pid {
+                if let (Some(recorded), Some(actual)) = (state.start_time, process_start_time(pid)) {
+                    return recorded == actual;
+                }
+            }
+        }
+
+        // Without a recorded start time, require the daemon's socket to exist
+        self.get_socket_path().map(|p| p.exists()).unwrap_or(false)
+    }
+
+    fn get_socket_path(&self) -> Result<PathBuf> {
+        // Read daemon PID from file
+        let pid_str = fs::read_to_string(&self.pid_file)
+            .context("Failed to read PID file")?;
+        let pid = pid_str.trim();
+
+        // Socket path includes PID to avoid conflicts
+        Ok(PathBuf::from("/tmp/.mcp").join(format!("{}-{}.sock", self.label(), pid)))
+    }
+
+    fn is_running(&self) -> Result<bool> {
+        if !self.pid_file.exists() {
+            return Ok(false);
+        }
+
+        let pid_str = fs::read_to_string(&self.pid_file)
+            .context("Failed to read PID file")?;
+        let pid = pid_str.trim().parse::<i32>()
+            .with_context(|| format!("Invalid PID in file: '{}'", pid_str.trim()))?;
+
+        // Check if process exists using kill with signal 0
+        // This doesn't send any signal but checks if process exists and we have permission
+        let alive = match kill(Pid::from_raw(pid), None) {
+            Ok(_) => true,  // Process exists
+            Err(nix::errno::Errno::ESRCH) => false,  // No such process
+            Err(nix::errno::Errno::EPERM) => true,   // Process exists but no permission
+            Err(_) => false,  // Other errors, assume not running
+        };
+
+        // A live PID may still belong to an unrelated process (stale PID file after reboot)
+        Ok(alive && self.owns_pid(pid))
+    }
+
+    fn start(&self, profile: &ServerProfile, launch: &DaemonLaunch) -> Result<()> {
+        let options = &launch.options;
+        if !profile.supports_daemon && options.keep_alive.is_none() {
+            return Err(anyhow!(
+                "Server '{}' does not support daemon mode (supports_daemon: false)",
+                self.server_name
+            ));
+        }
+
+        if self.is_running()? {
+            return Err(anyhow!("Daemon already running for '{}'", self.server_name));
+        }
+
+        let project = get_project_path();
+        eprintln!("Project: {}", project);
+        eprintln!("Profile: {}", self.pid_file.parent().unwrap().display());
+
+        run_hook("pre_start", &profile.pre_start, profile, &self.server_name)?;
+
+        eprintln!("Starting MCP daemon for '{}'...", self.server_name);
+
+        // Build daemon command
+        let mut cmd = Command::new(std::env::current_exe()?);
+        cmd.arg("__internal_daemon");
+        cmd.arg("--server");
+        cmd.arg(&self.server_name);
+
+        cmd.args(launch.daemon_args()?);
+        for name in DAEMON_SCOPE_ENV {
+            cmd.env_remove(name);
+        }
+        cmd.envs(daemon_scope_env());
+
+        // Create log file for daemon stderr; an appended one gets a separator per start
+        let profile_dir = self.pid_file.parent().unwrap();
+        let log_path = self.log_file();
+        let log_file = if options.append_log {
+            if fs::metadata(&log_path).is_ok_and(|m| m.len() >= DAEMON_LOG_MAX_BYTES) {
+                rotate_log(&log_path);
+            }
+            let mut file = open_append(&log_path)?;
+            writeln!(file, "===== [{}] Starting daemon for '{}' =====", timestamp_rfc3339(), self.server_name)?;
+            file
+        } else {
+            std::fs::File::create(&log_path)
+                .context("Failed to create daemon log file")?
+        };
+        // Where this start's output begins, so an earlier run's error isn't reported
+        let log_start = log_file.metadata().map(|m| m.len()).unwrap_or(0).saturating_sub(1) as usize;
+
+        // The daemon writes stats.json once it is ready, so a stale one must go
+        fs::remove_file(&self.stats_file).ok();
+        fs::remove_file(self.shutdown_file()).ok();
+
+        // Fork daemon process with proper daemonization
+        let mut child = unsafe {
+            cmd.pre_exec(|| {
+                // Create new session to detach from controlling terminal
+                setsid().map_err(|e| std::io::Error::from_raw_os_error(e as i32))?;
+                Ok(())
+            })
+        }
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::from(log_file))
+        .spawn()
+        .context("Failed to spawn daemon process")?;
+
+        let child_pid = child.id();
+
+        // Write PID file; the daemon writes daemon.json itself
         fs::write(&self.pid_file, child_pid.to_string())
             .context("Failed to write PID file")?;
 
-        // Construct expected socket path based on child PID
-        let expected_socket = PathBuf::from("/tmp/.mcp")
-            .join(format!("{}-{}.sock", self.server_name, child_pid));
+        // Construct expected socket path based on child PID
+        let expected_socket = PathBuf::from("/tmp/.mcp")
+            .join(format!("{}-{}.sock", daemon_label(&self.server_name), child_pid));
+
+        // Wait until the daemon is ready: server initialized, health check and
+        // warmups done (stats.json written), or until it exits with an error.
+        // Short timeouts poll more often so a fast server isn't held up.
+        let timeout = options.start_timeout
+            .or(profile.daemon_start_timeout_secs.map(Duration::from_secs))
+            .unwrap_or(DAEMON_READY_TIMEOUT);
+        let poll = (timeout / 100).clamp(Duration::from_millis(10), Duration::from_millis(100));
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if expected_socket.exists() && self.stats_file.exists() {
+                eprintln!("Daemon started (PID: {})", child_pid);
+                eprintln!("Socket: {}", expected_socket.display());
+                return Ok(());
+            }
+            if let Ok(Some(status)) = child.try_wait() {
+                self.remove_state_files();
+                // The daemon's own error report is the last thing in its log
+                let log = fs::read_to_string(&log_path).unwrap_or_default();
+                let log = log.get(log_start..).unwrap_or(&log);
+                let reason = log.rfind("\nError: ")
+                    .map(|i| log[i + "\nError: ".len()..].trim_end())
+                    .unwrap_or("see the log");
+                return Err(Failure::Unavailable.wrap(anyhow!(
+                    "Daemon process exited during startup ({}): {}\nCheck {}/daemon.log",
+                    status,
+                    reason,
+                    profile_dir.display()
+                )));
+            }
+            std::thread::sleep(poll);
+        }
+
+        // Timeout: don't leave a half-started daemon behind
+        kill(Pid::from_raw(child_pid as i32), Signal::SIGTERM).ok();
+        self.remove_state_files();
+        Err(Failure::Timeout.wrap(anyhow!(
+            "Daemon not ready within {}s (raise daemon_start_timeout_secs or --start-timeout). Check {}/daemon.log",
+            timeout.as_secs_f64(),
+            profile_dir.display()
+        )))
+    }
+
+    /// Removes the PID, state and stats files of a daemon that is gone
+    fn remove_state_files(&self) {
+        fs::remove_file(&self.pid_file).ok();
+        fs::remove_file(&self.state_file).ok();
+        fs::remove_file(&self.stats_file).ok();
+    }
+
+    /// Kills the daemon and its server's process group with SIGKILL and removes the
+    /// socket and state files in one go. Nothing is shut down gracefully: no
+    /// SIGTERM grace period, no container cleanup by the daemon, no `post_stop`.
+    fn reset(&self, container_runtime: Option<&str>) -> Result<()> {
+        let socket_path = self.get_socket_path().ok();
+        eprintln!("Project: {}", get_project_path());
+
+        if self.is_running()? {
+            let pid: i32 = fs::read_to_string(&self.pid_file)?.trim().parse()
+                .context("Invalid PID in file")?;
+            // The server leads its own process group, so this takes its children too
+            if let Some(server_pid) = self.read_state().and_then(|state| state.server_pid) {
+                kill(Pid::from_raw(-(server_pid as i32)), Signal::SIGKILL).ok();
+            }
+            kill(Pid::from_raw(pid), Signal::SIGKILL)
+                .context("Failed to send SIGKILL")?;
+            eprintln!("Killed daemon (PID: {}) and its server", pid);
+
+            // The daemon can no longer stop its container, so do it here
+            if let Some(runtime) = container_runtime {
+                let prefix = format!("mcp-valve-{}-", pid);
+                for entry in fs::read_dir(std::env::temp_dir())?.flatten() {
+                    let name = entry.file_name();
+                    let name = name.to_string_lossy();
+                    if name.starts_with(&prefix) && name.ends_with(".cid") {
+                        RunningContainer { runtime: runtime.to_string(), cidfile: entry.path() }.kill();
+                    }
+                }
+            }
+        } else {
+            eprintln!("Daemon is not running; removing leftover files");
+        }
+
+        self.remove_state_files();
+        if let Some(sp) = socket_path {
+            fs::remove_file(sp).ok();
+        }
+        eprintln!("Daemon reset");
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<()> {
+        if !self.is_running()? {
+            return Err(daemon_not_running_error(&self.server_name));
+        }
+
+        let project = get_project_path();
+        let pid_str = fs::read_to_string(&self.pid_file)?;
+        let pid: i32 = pid_str.trim().parse()
+            .context("Invalid PID in file")?;
+
+        let socket_path = self.get_socket_path().ok();
+
+        eprintln!("Project: {}", project);
+        eprintln!("Stopping daemon (PID: {})...", pid);
+
+        // Send SIGTERM
+        kill(Pid::from_raw(pid), Signal::SIGTERM)
+            .context("Failed to send SIGTERM")?;
+
+        // Wait for graceful shutdown; once the daemon reports it is shutting down,
+        // wait for its server and `post_stop` hook however long they take
+        let mut waited = 0;
+        let mut waiting_on_hook = false;
+        while waited < 10 || waiting_on_hook {
+            if !self.is_running()? {
+                fs::remove_file(&self.pid_file).ok();
+                fs::remove_file(&self.state_file).ok();
+                fs::remove_file(&self.stats_file).ok();
+                if let Some(ref sp) = socket_path {
+                    if sp.exists() {
+                        fs::remove_file(sp).ok();
+                    }
+                }
+                let shutdown = fs::read_to_string(self.shutdown_file()).unwrap_or_default();
+                fs::remove_file(self.shutdown_file()).ok();
+                eprintln!("Daemon stopped");
+                return match shutdown.strip_prefix("failed: ") {
+                    Some(error) => Err(anyhow!("{}", error)),
+                    None => Ok(()),
+                };
+            }
+            if !waiting_on_hook {
+                waiting_on_hook = fs::read_to_string(self.shutdown_file()).is_ok_and(|s| s == "stopping");
+            }
+            if waiting_on_hook && waited == 10 {
+                eprintln!("Waiting for the daemon to stop its server and run post_stop...");
+            }
+            std::thread::sleep(Duration::from_millis(500));
+            waited += 1;
+        }
+
+        // Force kill
+        kill(Pid::from_raw(pid), Signal::SIGKILL)
+            .context("Failed to send SIGKILL")?;
+
+        fs::remove_file(&self.pid_file).ok();
+        fs::remove_file(&self.state_file).ok();
+        fs::remove_file(&self.stats_file).ok();
+        if let Some(ref sp) = socket_path {
+            if sp.exists() {
+                fs::remove_file(sp).ok();
+            }
+        }
+
+        eprintln!("Daemon stopped (forced)");
+        Ok(())
+    }
+
+    fn status(&self) -> Result<()> {
+        let project = get_project_path();
+        let profile_dir = self.pid_file.parent().unwrap();
+        println!("Project: {}", project);
+        println!("Server: {}", self.server_name);
+        if let Some(instance) = daemon_instance() {
+            println!("Instance: {}", instance);
+        }
+        let absolute_dir = std::path::absolute(profile_dir).unwrap_or_else(|_| profile_dir.to_path_buf());
+        println!("Profile: {}", absolute_dir.display());
+
+        if self.is_running()? {
+            let pid_str = fs::read_to_string(&self.pid_file)?;
+            let socket_path = self.get_socket_path()?;
+            println!("Daemon is running");
+            println!("  PID: {}", pid_str.trim());
+            let state = self.read_state();
+            if let Some(server_pid) = state.as_ref().and_then(|state| state.server_pid) {
+                println!("  Server PID: {}", server_pid);
+            }
+            if let Some(args) = state.and_then(|state| state.launch?.server_args) {
+                println!("  Server args: {}", serde_json::to_string(&args)?);
+            }
+            println!("  Socket: {}", socket_path.display());
+            if let Some(summary) = self.stats_summary() {
+                println!("  {}", summary);
+            }
+        } else {
+            println!("Daemon is not running");
+            if self.pid_file.exists() {
+                eprintln!("{} Stale PID file found, cleaning up...", stderr_label("Warning:", COLOR_WARNING));
+                let socket_path = self.get_socket_path().ok();
+                fs::remove_file(&self.pid_file).ok();
+                fs::remove_file(&self.state_file).ok();
+                fs::remove_file(&self.stats_file).ok();
+                if let Some(sp) = socket_path {
+                    if sp.exists() {
+                        fs::remove_file(&sp).ok();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod pid_reuse_tests {
+    use super::*;
+
+    /// A profile dir whose PID file names this (live) test process
+    fn manager_for_own_pid(name: &str, start_time: Option<u64>) -> (DaemonManager, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("mcp-valve-test-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        let manager = DaemonManager::at("test", &dir);
+        let pid = std::process::id();
+        fs::write(&manager.pid_file, pid.to_string()).unwrap();
+        manager.write_state(&DaemonState { pid, start_time, ..Default::default() }).unwrap();
+        (manager, dir)
+    }
+
+    #[test]
+    fn live_pid_with_other_start_time_is_not_our_daemon() {
+        let actual = process_start_time(std::process::id() as i32).unwrap();
+        let (manager, dir) = manager_for_own_pid("reused", Some(actual + 1));
+        let running = manager.is_running().unwrap();
+        fs::remove_dir_all(&dir).ok();
+        assert!(!running);
+    }
+
+    #[test]
+    fn live_pid_with_recorded_start_time_is_our_daemon() {
+        let actual = process_start_time(std::process::id() as i32).unwrap();
+        let (manager, dir) = manager_for_own_pid("owned", Some(actual));
+        let running = manager.is_running().unwrap();
+        fs::remove_dir_all(&dir).ok();
+        assert!(running);
+    }
+}
+
+/// Removes what crashed daemons leave behind: stale PID/state files and sockets in
+/// the profile dirs, and sockets in `/tmp/.mcp` whose daemon PID is gone. With `all`,
+/// also deletes the profile dirs of dead daemons and of servers no longer in the config.
+fn prune(config: &ServerConfig, all: bool, dry_run: bool) -> Result<()> {
+    let mut removed: Vec<PathBuf> = Vec::new();
+    let remove = |removed: &mut Vec<PathBuf>, path: &Path, reason: &str| -> Result<()> {
+        println!("{} {} ({})", if dry_run { "Would remove" } else { "Removed" }, path.display(), reason);
+        removed.push(path.to_path_buf());
+        if dry_run {
+            return Ok(());
+        }
+        let result = if path.is_dir() { fs::remove_dir_all(path) } else { fs::remove_file(path) };
+        result.with_context(|| format!("Failed to remove {}", path.display()))
+    };
+
+    let root = profile_root();
+    let mut dirs: Vec<PathBuf> = fs::read_dir(&root)
+        .map(|entries| entries.flatten().map(|e| e.path()).filter(|p| p.is_dir()).collect())
+        .unwrap_or_default();
+    dirs.sort();
+    for dir in dirs {
+        let label = dir.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let server = label.split('@').next().unwrap_or_default();
+        let configured = config.servers.keys().any(|name| sanitize_server_name(name) == server);
+        let daemon = DaemonManager::at(server, &dir);
+        if daemon.is_running().unwrap_or(false) {
+            continue;
+        }
+
+        let stale = daemon.pid_file.exists();
+        if all && (stale || !configured) {
+            let reason = if configured { "daemon not running" } else { "server not in config" };
+            remove(&mut removed, &dir, reason)?;
+            continue;
+        }
+        if let Ok(socket) = daemon.get_socket_path() {
+            if socket.exists() {
+                remove(&mut removed, &socket, "daemon not running")?;
+            }
+        }
+        for file in [&daemon.pid_file, &daemon.state_file, &daemon.stats_file] {
+            if file.exists() {
+                remove(&mut removed, file, "daemon not running")?;
+            }
+        }
+        if !configured {
+            eprintln!("{}: server '{}' is not in the config (--all removes the directory)", dir.display(), server);
+        }
+    }
+
+    // Sockets are named <label>-<pid>.sock; keep any whose PID is still alive
+    let mut sockets: Vec<PathBuf> = fs::read_dir("/tmp/.mcp")
+        .map(|entries| entries.flatten().map(|e| e.path()).collect())
+        .unwrap_or_default();
+    sockets.sort();
+    sockets.retain(|socket| !removed.contains(socket));
+    for socket in sockets {
+        let Some(stem) = socket.file_name().and_then(|n| n.to_str()).and_then(|n| n.strip_suffix(".sock")) else {
+            continue;
+        };
+        let Some(pid) = stem.rsplit_once('-').and_then(|(_, pid)| pid.parse::<i32>().ok()) else {
+            continue;
+        };
+        if socket.exists() && kill(Pid::from_raw(pid), None) == Err(Errno::ESRCH) {
+            remove(&mut removed, &socket, "daemon PID gone")?;
+        }
+    }
+
+    let count = removed.len();
+    eprintln!(
+        "{} {} stale entr{}",
+        if dry_run { "Would prune" } else { "Pruned" },
+        count,
+        if count == 1 { "y" } else { "ies" }
+    );
+    Ok(())
+}
+
+// ============================================================================
+// Unix Socket Communication
+// ============================================================================
+
+/// Set by SIGHUP; the daemon reloads its profile before accepting the next client
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_reload(_: c_int) {
+    RELOAD_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// How long `start-daemon` waits by default for the server to initialize and pass its checks
+const DAEMON_READY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often the idle daemon wakes to check its server and idle timer
+const DAEMON_TICK_MS: u16 = 1000;
+
+/// Whether `new` starts the server differently from `old`. These are the fields
+/// a SIGHUP reload applies; `default_args` only matter without `--server-args`.
+fn launch_changed(old: &ServerProfile, new: &ServerProfile, extra_args: &Option<Vec<String>>) -> bool {
+    old.command != new.command
+        || old.container != new.container
+        || (extra_args.is_none() && old.default_args != new.default_args)
+        || old.env != new.env
+        || old.clear_env != new.clear_env
+        || old.env_passthrough != new.env_passthrough
+        || old.initialize_params != new.initialize_params
+        || old.cwd != new.cwd
+}
+
+/// Runs a profile's warmup calls; only a failure of a required warmup is returned
+fn run_warmups(mcp: &mut McpClient, profile: &ServerProfile) -> Result<()> {
+    for warmup in &profile.warmup {
+        eprintln!("Warmup: {} {}", warmup.tool, warmup.args);
+        match mcp.call_tool(&warmup.tool, warmup.args.clone()) {
+            Ok(_) => eprintln!("Warmup '{}' succeeded", warmup.tool),
+            Err(e) if profile.warmup_required => {
+                return Err(e.context(format!("Required warmup '{}' failed", warmup.tool)));
+            }
+            Err(e) => eprintln!("Warmup '{}' failed: {}", warmup.tool, e),
+        }
+    }
+    Ok(())
+}
+
+/// Whether `actual` contains `expected`: objects by key (recursively), other values by equality
+fn json_contains(actual: &Value, expected: &Value) -> bool {
+    match (actual, expected) {
+        (Value::Object(actual), Value::Object(expected)) => expected
+            .iter()
+            .all(|(key, value)| actual.get(key).is_some_and(|a| json_contains(a, value))),
+        _ => actual == expected,
+    }
+}
+
+/// Whether a tool result's text mentions `text`: the `content[].text` blocks and the
+/// string leaves of `structuredContent`, never the surrounding JSON keys or metadata
+fn result_text_contains(result: &Value, text: &str) -> bool {
+    fn string_leaves_contain(value: &Value, text: &str) -> bool {
+        match value {
+            Value::String(s) => s.contains(text),
+            Value::Array(items) => items.iter().any(|v| string_leaves_contain(v, text)),
+            Value::Object(map) => map.values().any(|v| string_leaves_contain(v, text)),
+            _ => false,
+        }
+    }
+
+    result["content"].as_array().is_some_and(|blocks| {
+        blocks.iter().any(|b| b["text"].as_str().is_some_and(|t| t.contains(text)))
+    }) || string_leaves_contain(&result["structuredContent"], text)
+}
+
+#[cfg(test)]
+mod health_check_tests {
+    use super::*;
+
+    #[test]
+    fn text_expect_matches_content_and_structured_strings_only() {
+        let result = json!({
+            "content": [{"type": "text", "text": "status: ok"}],
+            "structuredContent": {"db": {"state": "connected"}},
+            "isError": false
+        });
+        assert!(result_text_contains(&result, "ok"));
+        assert!(result_text_contains(&result, "connected"));
+        assert!(!result_text_contains(&result, "isError"));
+        assert!(!result_text_contains(&result, "state"));
+        assert!(!result_text_contains(&result, "false"));
+    }
+}
+
+/// Runs the profile's health check, if any; an error means the server is unusable
+fn run_health_check(mcp: &mut McpClient, profile: &ServerProfile) -> Result<()> {
+    let Some(check) = &profile.health_check else {
+        return Ok(());
+    };
+    eprintln!("Health check: {} {}", check.call.tool, check.call.args);
+    let result = mcp.call_tool(&check.call.tool, check.call.args.clone())
+        .with_context(|| format!("Health check '{}' failed", check.call.tool))?;
+
+    let matched = match &check.expect {
+        None => true,
+        Some(Value::String(text)) => result_text_contains(&result, text),
+        Some(expected) => json_contains(&last_value(result.clone()), expected),
+    };
+    if !matched {
+        return Err(Failure::Unavailable.wrap(anyhow!(
+            "Health check '{}' result does not match expect {}: {}",
+            check.call.tool,
+            check.expect.as_ref().unwrap_or(&Value::Null),
+            result
+        )));
+    }
+    eprintln!("Health check '{}' passed", check.call.tool);
+    Ok(())
+}
+
+fn run_daemon(
+    server_name: &str,
+    profile: &ServerProfile,
+    daemon_launch: &DaemonLaunch,
+    reload_profile: &dyn Fn() -> Result<ServerProfile>,
+) -> Result<()> {
+    let extra_args = daemon_launch.server_args.clone();
+    let options = &daemon_launch.options;
+    let daemon_mgr = DaemonManager::new(server_name);
+    let pid = std::process::id();
+    let mut state = DaemonState {
+        pid,
+        start_time: process_start_time(pid as i32),
+        server_pid: None,
+        launch: Some(daemon_launch.clone()),
+    };
+    daemon_mgr.write_state(&state)?;
+
+    // Use /tmp for socket with daemon's own PID
+    let socket_dir = PathBuf::from("/tmp/.mcp");
+
+    // Ensure socket directory exists with secure permissions
+    if !socket_dir.exists() {
+        let old_umask = umask(Mode::from_bits_truncate(0o077));
+        fs::create_dir_all(&socket_dir)
+            .context("Failed to create socket directory")?;
+        umask(old_umask);
+    }
+
+    let socket_path = socket_dir.join(format!("{}-{}.sock", daemon_label(server_name), std::process::id()));
+
+    // Clean up old socket
+    if socket_path.exists() {
+        fs::remove_file(&socket_path)?;
+    }
+
+    let listener = UnixListener::bind(&socket_path)
+        .context("Failed to bind Unix socket")?;
+
+    // Restrict socket permissions to owner only (0600)
+    fs::set_permissions(&socket_path, fs::Permissions::from_mode(0o600))
+        .context("Failed to set socket permissions")?;
+
+    eprintln!("Daemon listening on {:?}", socket_path);
+
+    // Starts the MCP server and primes it before it serves clients, to hide
+    // cold-start latency; also used for reloads and timeout restarts
+    let launch = |profile: &ServerProfile| -> Result<McpClient> {
+        let mut mcp = McpClient::start(profile, extra_args.clone(), server_name, options)?;
+        run_health_check(&mut mcp, profile).and_then(|()| run_warmups(&mut mcp, profile))?;
+        Ok(mcp)
+    };
+
+    let mut mcp = match launch(profile) {
+        Ok(mcp) => mcp,
+        Err(e) => {
+            fs::remove_file(&socket_path).ok();
+            return Err(e);
+        }
+    };
+    daemon_mgr.record_server_pid(&mut state, mcp.server_pid());
+
+    let mut stats = DaemonStats {
+        started_at: unix_now(),
+        ..Default::default()
+    };
+    daemon_mgr.write_stats(&stats).ok();
+
+    let mut profile = profile.clone();
+    unsafe {
+        let _ = signal(Signal::SIGHUP, SigHandler::Handler(request_reload));
+        // Exit through `Drop` so container servers are stopped too
+        let _ = signal(Signal::SIGTERM, SigHandler::Handler(set_interrupted));
+    }
+    stop_on_interrupt();
+
+    // Accept only after poll reports a client, so a vanished one can't block us
+    listener.set_nonblocking(true).context("Failed to set socket non-blocking")?;
+    let mut last_request = Instant::now();
+
+    // Handle connections; the wait wakes on signals and every tick, so reloads,
+    // shutdown and the checks below run between requests even with no clients.
+    // Every way out breaks with the daemon's result, so `post_stop` runs however it stops
+    let result = loop {
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            eprintln!("[{}] Shutting down", timestamp_rfc3339());
+            fs::remove_file(&socket_path).ok();
+            break Ok(());
+        }
+
+        if let Some(status) = mcp.exit_status() {
+            eprintln!("[{}] MCP server exited ({}), shutting down", timestamp_rfc3339(), status);
+            fs::remove_file(&socket_path).ok();
+            daemon_mgr.remove_state_files();
+            break Err(anyhow!("MCP server exited ({})", status));
+        }
+
+        if let Some(idle) = profile.idle_timeout {
+            if last_request.elapsed() >= Duration::from_secs(idle) {
+                eprintln!("[{}] Idle for {}s, shutting down", timestamp_rfc3339(), idle);
+                fs::remove_file(&socket_path).ok();
+                daemon_mgr.remove_state_files();
+                break Ok(());
+            }
+        }
+
+        if RELOAD_REQUESTED.swap(false, Ordering::SeqCst) {
+            eprintln!("[{}] SIGHUP: reloading config", timestamp_rfc3339());
+            match reload_profile() {
+                Ok(new_profile) if !launch_changed(&profile, &new_profile, &extra_args) => {
+                    eprintln!("Config unchanged, keeping the running server");
+                    profile = new_profile;
+                }
+                Ok(new_profile) => {
+                    eprintln!("Config changed, restarting MCP server");
+                    match launch(&new_profile) {
+                        Ok(new_mcp) => {
+                            mcp = new_mcp;
+                            profile = new_profile;
+                            daemon_mgr.record_server_pid(&mut state, mcp.server_pid());
+                            eprintln!("Reload complete");
+                        }
+                        Err(e) => eprintln!("Reload failed, keeping the previous server: {:#}", e),
+                    }
+                }
+                Err(e) => eprintln!("Reload failed, keeping the previous server: {:#}", e),
+            }
+        }
+
+        let mut fds = [PollFd::new(listener.as_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, PollTimeout::from(DAEMON_TICK_MS)) {
+            Ok(0) | Err(Errno::EINTR) => continue,
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Connection error: {}", e);
+                continue;
+            }
+        }
+
+        match listener.accept() {
+            Ok((stream, _)) => {
+                mcp.keep_on_timeout = profile.restart_after_timeouts.is_some();
+                let result = stream.set_nonblocking(false)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|()| handle_client(&mut mcp, &profile, stream));
+                if let Err(e) = result {
+                    eprintln!("Client error: {}", e);
+                }
+                last_request = Instant::now();
+                stats.requests += 1;
+                stats.last_activity = Some(unix_now());
+                daemon_mgr.write_stats(&stats).ok();
+
+                // A server that keeps hanging is replaced rather than left to block every request
+                let limit = profile.restart_after_timeouts.unwrap_or(u32::MAX).max(1);
+                if mcp.consecutive_timeouts >= limit {
+                    eprintln!(
+                        "[{}] {} request timeouts in a row, restarting MCP server",
+                        timestamp_rfc3339(),
+                        mcp.consecutive_timeouts
+                    );
+                    match launch(&profile) {
+                        Ok(new_mcp) => {
+                            mcp = new_mcp;
+                            daemon_mgr.record_server_pid(&mut state, mcp.server_pid());
+                            eprintln!("Restart complete");
+                        }
+                        Err(e) => {
+                            eprintln!("[{}] Restart failed, shutting down: {:#}", timestamp_rfc3339(), e);
+                            fs::remove_file(&socket_path).ok();
+                            daemon_mgr.remove_state_files();
+                            break Err(e);
+                        }
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+            Err(e) => {
+                eprintln!("Connection error: {}", e);
+            }
+        }
+    };
+
+    // The server goes first, so the hook sees it stopped. `stop` waits for both
+    // and reports a failed hook
+    let shutdown_file = daemon_mgr.shutdown_file();
+    fs::write(&shutdown_file, "stopping").ok();
+    drop(mcp);
+    match run_hook("post_stop", &profile.post_stop, &profile, server_name) {
+        Ok(()) => fs::write(&shutdown_file, "ok").ok(),
+        Err(e) => {
+            eprintln!("[{}] {:#}", timestamp_rfc3339(), e);
+            fs::write(&shutdown_file, format!("failed: {:#}", e)).ok()
+        }
+    };
+    result
+}
+
+/// Builds the daemon's JSON-RPC response for a request id and its outcome
+fn rpc_response(id: &Value, result: Result<Value>) -> Value {
+    match result {
+        Ok(result) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": result
+        }),
+        Err(e) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": rpc_error_object(&e)
+        }),
+    }
+}
+
+/// The daemon's error object: the message, the failure kind, plus the server's
+/// JSON-RPC code and data if any
+fn rpc_error_object(e: &anyhow::Error) -> Value {
+    let mut error = json!({"message": e.to_string()});
+    if let Some(failure) = Failure::of(e) {
+        error["kind"] = json!(failure.name());
+    }
+    if let Some(rpc) = e.downcast_ref::<RpcError>() {
+        for field in ["code", "data"] {
+            if let Some(value) = rpc.error.get(field) {
+                error[field] = value.clone();
+            }
+        }
+    }
+    error
+}
+
+/// Refuses tools outside a non-empty allowlist
+fn check_tool_allowed(allowed_tools: &[String], name: &str) -> Result<()> {
+    if allowed_tools.is_empty() || allowed_tools.iter().any(|t| t == name) {
+        return Ok(());
+    }
+    Err(anyhow!(
+        "Tool '{}' is not allowed for this server (allowed: {})",
+        name,
+        allowed_tools.join(", ")
+    ))
+}
+
+/// Tool name patterns refused in read-only mode when the profile sets no `deny_tools`
+const DEFAULT_DENY_TOOLS: [&str; 8] = [
+    "*write*", "*delete*", "*remove*", "*create*", "*update*", "*navigate*", "*click*", "*exec*",
+];
+
+/// Refuses a call in read-only mode, describing the call it would have made
+fn check_read_only(profile: &ServerProfile, name: &str, args: &Value) -> Result<()> {
+    if !profile.read_only {
+        return Ok(());
+    }
+    let lower = name.to_lowercase();
+    let denied = if profile.deny_tools.is_empty() {
+        DEFAULT_DENY_TOOLS.iter().any(|p| wildcard_match(p, &lower))
+    } else {
+        profile.deny_tools.iter().any(|p| wildcard_match(&p.to_lowercase(), &lower))
+    };
+    if !denied {
+        return Ok(());
+    }
+    Err(anyhow!(
+        "Read-only mode: refused to call '{}' with arguments {}",
+        name,
+        if args.is_null() { json!({}) } else { args.clone() }
+    ))
+}
+
+/// A call's arguments deep-merged over the profile's `tool_defaults`: the `*`
+/// entry first, then the tool's own, then `args`, so the caller always wins
+fn with_tool_defaults(tool_defaults: &HashMap<String, serde_json::Map<String, Value>>, tool: &str, args: Value) -> Value {
+    let defaults: Vec<_> = ["*", tool].iter().filter_map(|key| tool_defaults.get(*key)).collect();
+    if defaults.is_empty() {
+        return args;
+    }
+    let mut merged = Value::Object(serde_json::Map::new());
+    for fields in defaults {
+        merge_json(&mut merged, &Value::Object(fields.clone()));
+    }
+    if !args.is_null() {
+        merge_json(&mut merged, &args);
+    }
+    merged
+}
+
+/// Applies `--only-tool` (replacing `allowed_tools`) and `--read-only` to the profile
+/// of a server this process runs, as a daemon or directly
+fn apply_policy_overrides(profile: &mut ServerProfile, only_tools: &[String], read_only: bool) {
+    if !only_tools.is_empty() {
+        profile.allowed_tools = only_tools.to_vec();
+    }
+    profile.read_only |= read_only;
+}
+
+/// A call's arguments with the profile's `tool_defaults` merged in, or an error if
+/// its allowlist or read-only policy refuses the call; what a daemon would send
+fn checked_tool_args(profile: &ServerProfile, tool: &str, args: Value) -> Result<Value> {
+    let args = with_tool_defaults(&profile.tool_defaults, tool, args);
+    check_tool_call(profile, tool, &args)?;
+    Ok(args)
+}
+
+/// Applies the daemon's allowlist and read-only policy to one tool call
+fn check_tool_call(profile: &ServerProfile, name: &str, args: &Value) -> Result<()> {
+    check_tool_allowed(&profile.allowed_tools, name)?;
+    check_read_only(profile, name, args)
+}
+
+/// Applies a client request's `client_policy` on top of the daemon's own policy, so a
+/// daemon started without `--read-only` / `--only-tool` still honors them for a client
+/// that passes them. A client can only tighten the daemon's policy, never loosen it
+fn check_client_policy(profile: &ServerProfile, policy: &Value, name: &str, args: &Value) -> Result<()> {
+    check_client_only_tools(policy, name)?;
+    if policy["read_only"] == true && !profile.read_only {
+        check_read_only(&ServerProfile { read_only: true, ..profile.clone() }, name, args)?;
+    }
+    Ok(())
+}
+
+/// Refuses tools outside the client's `--only-tool` list, if it sent one
+fn check_client_only_tools(policy: &Value, name: &str) -> Result<()> {
+    let Some(only_tools) = policy["only_tools"].as_array().filter(|tools| !tools.is_empty()) else {
+        return Ok(());
+    };
+    if only_tools.iter().any(|t| t == name) {
+        return Ok(());
+    }
+    Err(anyhow!(
+        "Tool '{}' is not allowed by --only-tool (allowed: {})",
+        name,
+        only_tools.iter().filter_map(Value::as_str).collect::<Vec<_>>().join(", ")
+    ))
+}
+
+/// `check_tool_call` plus the policy the client sent with `request`
+fn check_requested_call(profile: &ServerProfile, request: &Value, name: &str, args: &Value) -> Result<()> {
+    check_tool_call(profile, name, args)?;
+    check_client_policy(profile, &request["client_policy"], name, args)
+}
+
+#[cfg(test)]
+mod client_policy_tests {
+    use super::*;
+
+    fn profile() -> ServerProfile {
+        serde_json::from_value(json!({"command": ["server"]})).unwrap()
+    }
+
+    #[test]
+    fn read_only_client_is_refused_by_a_daemon_started_without_it() {
+        let daemon = profile();
+        let request = json!({"method": "tools/call", "client_policy": {"read_only": true}});
+        assert!(check_requested_call(&daemon, &request, "write_file", &json!({})).is_err());
+        assert!(check_requested_call(&daemon, &request, "read_file", &json!({})).is_ok());
+    }
+
+    #[test]
+    fn only_tool_client_is_limited_by_a_daemon_started_without_it() {
+        let daemon = profile();
+        let request = json!({"method": "tools/call", "client_policy": {"only_tools": ["search"]}});
+        assert!(check_requested_call(&daemon, &request, "search", &json!({})).is_ok());
+        assert!(check_requested_call(&daemon, &request, "delete_all", &json!({})).is_err());
+    }
+
+    #[test]
+    fn only_tool_client_cannot_widen_the_daemon_allowlist() {
+        let daemon = ServerProfile { allowed_tools: vec!["search".to_string()], ..profile() };
+        let request = json!({"method": "tools/call", "client_policy": {"only_tools": ["delete_all"]}});
+        assert!(check_requested_call(&daemon, &request, "delete_all", &json!({})).is_err());
+    }
+
+    /// `call --args-stdin-jsonl` and the TUI without a daemon check each call this way
+    #[test]
+    fn direct_calls_honor_only_tool_and_read_only() {
+        let mut only_echo = profile();
+        apply_policy_overrides(&mut only_echo, &["echo".to_string()], false);
+        assert!(checked_tool_args(&only_echo, "delete_all", json!({})).is_err());
+        assert_eq!(checked_tool_args(&only_echo, "echo", json!({"text": "a"})).unwrap(), json!({"text": "a"}));
+
+        let mut read_only = profile();
+        apply_policy_overrides(&mut read_only, &[], true);
+        assert!(checked_tool_args(&read_only, "write_file", json!({})).is_err());
+        assert!(checked_tool_args(&read_only, "read_file", json!({})).is_ok());
+    }
+
+    #[test]
+    fn direct_calls_check_the_merged_arguments() {
+        let profile: ServerProfile = serde_json::from_value(json!({
+            "command": ["server"],
+            "tool_defaults": {"echo": {"n": 1}}
+        }))
+        .unwrap();
+        assert_eq!(checked_tool_args(&profile, "echo", json!({"text": "a"})).unwrap(), json!({"n": 1, "text": "a"}));
+    }
+
+    #[test]
+    fn requests_without_a_policy_get_the_daemon_policy() {
+        let daemon = profile();
+        let request = json!({"method": "tools/call"});
+        assert!(check_requested_call(&daemon, &request, "write_file", &json!({})).is_ok());
+
+        let read_only = ServerProfile { read_only: true, ..profile() };
+        assert!(check_requested_call(&read_only, &request, "write_file", &json!({})).is_err());
+    }
+}
+
+/// Set by `--compress`: daemon requests ask for gzipped responses
+static ACCEPT_GZIP: AtomicBool = AtomicBool::new(false);
+
+/// Set by `--read-only`: daemon requests carry it as their `client_policy`
+static CLIENT_READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Set by `--only-tool`: daemon requests carry it as their `client_policy`
+static CLIENT_ONLY_TOOLS: OnceLock<Vec<String>> = OnceLock::new();
+
+/// Adds the client's `--read-only` / `--only-tool` to a daemon request (or each
+/// request of a batch), for a daemon that was started without them
+fn mark_client_policy(request: &mut Value) {
+    let mut policy = serde_json::Map::new();
+    if CLIENT_READ_ONLY.load(Ordering::SeqCst) {
+        policy.insert("read_only".to_string(), json!(true));
+    }
+    if let Some(only_tools) = CLIENT_ONLY_TOOLS.get().filter(|tools| !tools.is_empty()) {
+        policy.insert("only_tools".to_string(), json!(only_tools));
+    }
+    if !policy.is_empty() {
+        set_client_policy(request, &Value::Object(policy));
+    }
+}
+
+fn set_client_policy(request: &mut Value, policy: &Value) {
+    match request {
+        Value::Array(batch) => batch.iter_mut().for_each(|r| set_client_policy(r, policy)),
+        Value::Object(fields) => {
+            fields.insert("client_policy".to_string(), policy.clone());
+        }
+        _ => {}
+    }
+}
+
+/// Responses at least this large are gzipped for clients that accept it. Below
+/// this, compressing costs more time than the socket copy it saves.
+const GZIP_MIN_BYTES: usize = 256 * 1024;
+
+/// Marks a daemon request (or each request of a batch) as accepting gzip
+fn accept_gzip(request: &mut Value) {
+    match request {
+        Value::Array(batch) => batch.iter_mut().for_each(accept_gzip),
+        Value::Object(fields) => {
+            fields.insert("accept_encoding".to_string(), json!("gzip"));
+        }
+        _ => {}
+    }
+}
+
+/// Writes a response line, or for large responses to a client that accepts gzip,
+/// a `{"content_encoding": "gzip", "length": N}` line followed by N compressed bytes
+fn write_daemon_response(stream: &mut UnixStream, response: &Value, gzip: bool) -> Result<()> {
+    let body = serde_json::to_string(response)?;
+    if !gzip || body.len() < GZIP_MIN_BYTES {
+        writeln!(stream, "{}", body)?;
+        return Ok(());
+    }
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(body.as_bytes())?;
+    let compressed = encoder.finish()?;
+    writeln!(stream, "{}", json!({"content_encoding": "gzip", "length": compressed.len()}))?;
+    stream.write_all(&compressed)?;
+    Ok(())
+}
+
+/// Reads a daemon response written by `write_daemon_response`
+fn read_daemon_response(reader: &mut BufReader<UnixStream>) -> Result<Value> {
+    let parse = |text: &str| {
+        serde_json::from_str::<Value>(text)
+            .context("Invalid JSON-RPC response")
+            .map_err(|e| Failure::Protocol.wrap(e))
+    };
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let header = parse(line.trim())?;
+    if header["content_encoding"] != "gzip" {
+        return Ok(header);
+    }
+
+    let length = header["length"].as_u64()
+        .ok_or_else(|| Failure::Protocol.wrap(anyhow!("Compressed response without length")))?;
+    let mut compressed = vec![0; length as usize];
+    reader.read_exact(&mut compressed)?;
+    let mut body = String::new();
+    GzDecoder::new(compressed.as_slice())
+        .read_to_string(&mut body)
+        .context("Invalid compressed response")
+        .map_err(|e| Failure::Protocol.wrap(e))?;
+    parse(&body)
+}
+
+/// Methods `handle_client` answers through a dedicated `McpClient` method
+const DAEMON_METHODS: [&str; 7] = [
+    "tools/call", "tools/list", "resources/list", "resources/read", "prompts/list", "prompts/get", "completion/complete",
+];
+
+/// Standard MCP methods without a dedicated `McpClient` method, which the daemon
+/// forwards to the server unchanged
+const DAEMON_PASSTHROUGH_METHODS: [&str; 3] = ["ping", "resources/templates/list", "logging/setLevel"];
+
+fn handle_client(mcp: &mut McpClient, profile: &ServerProfile, mut stream: UnixStream) -> Result<()> {
+    const MAX_REQUEST_SIZE: usize = 1024 * 1024; // 1MB limit
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::with_capacity(8192);
+    reader.read_line(&mut line)?;
+
+    if line.len() > MAX_REQUEST_SIZE {
+        return Err(anyhow!("Request too large: {} bytes", line.len()));
+    }
+
+    let request: Value = serde_json::from_str(line.trim())
+        .context("Invalid JSON-RPC request")?;
+    record_frame("client", "recv", &request);
+    let gzip = match &request {
+        Value::Array(batch) => batch.iter().any(|r| r["accept_encoding"] == "gzip"),
+        request => request["accept_encoding"] == "gzip",
+    };
+
+    // A JSON array is a batch of tools/call requests
+    if let Some(batch) = request.as_array() {
+        // Entries without a tool name or refused by policy are answered here; the
+        // rest go to the server as one batch
+        let mut permitted = Vec::new();
+        let mut refused = Vec::new();
+        for r in batch {
+            let call = r["params"]["name"].as_str()
+                .ok_or_else(|| anyhow!("Missing tool name in batch entry"))
+                .and_then(|name| {
+                    let args = with_tool_defaults(&profile.tool_defaults, name, r["params"]["arguments"].clone());
+                    check_requested_call(profile, r, name, &args)?;
+                    Ok((name.to_string(), args))
+                });
+            match call {
+                Ok(call) => permitted.push((r, call)),
+                Err(e) => refused.push(json!({"jsonrpc": "2.0", "id": r["id"], "error": rpc_error_object(&e)})),
+            }
+        }
+        let permitted_calls: Vec<_> = permitted.iter().map(|(_, call)| call.clone()).collect();
+
+        let results = if permitted_calls.is_empty() {
+            Ok(Vec::new())
+        } else {
+            mcp.call_batch(&permitted_calls)
+        };
+        let mut responses: Vec<Value> = match results {
+            Ok(results) => permitted
+                .iter()
+                .zip(results)
+                .map(|((r, _), result)| match result {
+                    Ok(result) => json!({"jsonrpc": "2.0", "id": r["id"], "result": result}),
+                    Err(e) => json!({"jsonrpc": "2.0", "id": r["id"], "error": rpc_error_object(&e)}),
+                })
+                .collect(),
+            Err(e) => permitted
+                .iter()
+                .map(|(r, _)| json!({"jsonrpc": "2.0", "id": r["id"], "error": rpc_error_object(&e)}))
+                .collect(),
+        };
+        responses.extend(refused);
+
+        let responses = Value::Array(responses);
+        record_frame("client", "send", &responses);
+        return write_daemon_response(&mut stream, &responses, gzip);
+    }
+
+    let method = request["method"].as_str()
+        .ok_or_else(|| anyhow!("Missing method"))?;
+
+    let params = &request["params"];
+    let response = match method {
+        "tools/call" => {
+            let tool_name = params["name"].as_str()
+                .ok_or_else(|| anyhow!("Missing tool name"))?;
+            let args = with_tool_defaults(&profile.tool_defaults, tool_name, params["arguments"].clone());
+
+            let result = check_requested_call(profile, &request, tool_name, &args)
+                .and_then(|()| mcp.call_tool(tool_name, args));
+            rpc_response(&request["id"], result)
+        }
+        "tools/list" => {
+            let result = mcp.list_tools().map(|mut result| {
+                if let Some(tools) = result["tools"].as_array_mut() {
+                    tools.retain(|tool| {
+                        tool["name"].as_str().is_some_and(|name| {
+                            check_tool_allowed(&profile.allowed_tools, name).is_ok()
+                                && check_client_only_tools(&request["client_policy"], name).is_ok()
+                        })
+                    });
+                }
+                result
+            });
+            rpc_response(&request["id"], result)
+        }
+        "resources/list" => rpc_response(&request["id"], mcp.list_resources()),
+        "resources/read" => {
+            let uri = params["uri"].as_str()
+                .ok_or_else(|| anyhow!("Missing resource uri"))?;
+            rpc_response(&request["id"], mcp.read_resource(uri))
+        }
+        "prompts/list" => rpc_response(&request["id"], mcp.list_prompts()),
+        "completion/complete" => {
+            let name = params["argument"]["name"].as_str()
+                .ok_or_else(|| anyhow!("Missing argument name"))?;
+            let partial = params["argument"]["value"].as_str().unwrap_or("");
+            rpc_response(&request["id"], mcp.complete(params["ref"].clone(), name, partial))
+        }
+        "prompts/get" => {
+            let name = params["name"].as_str()
+                .ok_or_else(|| anyhow!("Missing prompt name"))?;
+            rpc_response(&request["id"], mcp.get_prompt(name, params["arguments"].clone()))
+        }
+        // `raw-request`: any method, answered with the server's whole response
+        "valve/raw" => {
+            let raw_method = params["method"].as_str()
+                .ok_or_else(|| anyhow!("Missing raw method"))?;
+            let raw_params = params["params"].clone();
+            let allowed = match raw_method {
+                "tools/call" => check_requested_call(
+                    profile,
+                    &request,
+                    raw_params["name"].as_str().unwrap_or(""),
+                    &raw_params["arguments"],
+                ),
+                _ => Ok(()),
+            };
+            let result = allowed.and_then(|()| {
+                mcp.raw_request(raw_method, raw_params, params["notification"] == true)
+            });
+            rpc_response(&request["id"], result)
+        }
+        _ if DAEMON_PASSTHROUGH_METHODS.contains(&method) => {
+            let params = if params.is_null() { json!({}) } else { params.clone() };
+            rpc_response(&request["id"], mcp.request(method, params))
+        }
+        _ => json!({
+            "jsonrpc": "2.0",
+            "id": request["id"],
+            "error": {
+                "code": -32601,
+                "message": format!(
+                    "Unknown method: {} (the daemon handles {}; use raw-request without --result for anything else)",
+                    method,
+                    DAEMON_METHODS.iter().chain(&DAEMON_PASSTHROUGH_METHODS).copied().collect::<Vec<_>>().join(", ")
+                ),
+                "kind": Failure::Protocol.name()
+            }
+        }),
+    };
+
+    record_frame("client", "send", &response);
+    write_daemon_response(&mut stream, &response, gzip)
+}
+
+/// Socket timeouts for talking to a daemon, from `--timeout-*`
+#[derive(Debug, Clone, Copy)]
+struct DaemonTimeouts {
+    connect: Duration,
+    read: Duration,
+    write: Duration,
+}
+
+impl Default for DaemonTimeouts {
+    fn default() -> Self {
+        Self {
+            connect: Duration::from_secs(2),
+            read: Duration::from_secs(30),
+            write: Duration::from_secs(30),
+        }
+    }
+}
+
+static DAEMON_TIMEOUTS: OnceLock<DaemonTimeouts> = OnceLock::new();
+
+/// Parses a positive number of seconds, e.g. `2` or `0.5`
+fn parse_seconds(value: &str) -> std::result::Result<Duration, String> {
+    match value.parse::<f64>() {
+        Ok(secs) if secs > 0.0 && secs.is_finite() => Ok(Duration::from_secs_f64(secs)),
+        _ => Err(format!("expected a positive number of seconds, got '{}'", value)),
+    }
+}
+
+/// Parses an age like `90s`, `10m`, `1h` or `2d` (a bare number is seconds)
+fn parse_age(value: &str) -> std::result::Result<Duration, String> {
+    let split = value.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let scale = match unit {
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86_400.0,
+        _ => return Err(format!("unknown unit '{}' in '{}' (use s, m, h or d)", unit, value)),
+    };
+    parse_seconds(number)
+        .map(|secs| secs.mul_f64(scale))
+        .map_err(|_| format!("expected an age like 90s, 10m, 1h or 2d, got '{}'", value))
+}
+
+/// Connects without blocking past `timeout`. A live but unresponsive daemon
+/// (full accept backlog) makes a blocking connect hang indefinitely.
+fn connect_with_timeout(path: &Path, timeout: Duration) -> std::io::Result<UnixStream> {
+    let deadline = std::time::Instant::now() + timeout;
+    let fd = socket(AddressFamily::Unix, SockType::Stream, SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC, None)?;
+    let addr = UnixAddr::new(path)?;
+
+    loop {
+        match connect(fd.as_raw_fd(), &addr) {
+            Ok(()) => break,
+            // Linux reports a full backlog as EAGAIN; the connect must be retried
+            Err(Errno::EAGAIN) if std::time::Instant::now() < deadline => {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            Err(Errno::EAGAIN) => {
+                return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "connect timed out"));
+            }
+            Err(Errno::EINPROGRESS) => {
+                let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+                let mut fds = [PollFd::new(fd.as_fd(), PollFlags::POLLOUT)];
+                let ready = poll(&mut fds, PollTimeout::try_from(remaining).unwrap_or(PollTimeout::MAX))?;
+                if ready == 0 {
+                    return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "connect timed out"));
+                }
+                match getsockopt(&fd, sockopt::SocketError)? {
+                    0 => break,
+                    errno => return Err(std::io::Error::from_raw_os_error(errno)),
+                }
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let stream = UnixStream::from(fd);
+    stream.set_nonblocking(false)?;
+    Ok(stream)
+}
+
+fn connect_to_daemon(server_name: &str) -> Result<UnixStream> {
+    let daemon_mgr = DaemonManager::new(server_name);
+    let socket_path = daemon_mgr.get_socket_path()
+        .context("Failed to get socket path (daemon not started?)")?;
+    let timeouts = DAEMON_TIMEOUTS.get().copied().unwrap_or_default();
+
+    let stream = connect_with_timeout(&socket_path, timeouts.connect)
+        .map_err(|e| {
+            let failure = match e.kind() {
+                std::io::ErrorKind::TimedOut => Failure::Timeout,
+                _ => Failure::Unavailable,
+            };
+            failure.wrap(anyhow!(e).context("Failed to connect to daemon (is it running?)"))
+        })?;
+
+    stream.set_read_timeout(Some(timeouts.read))
+        .context("Failed to set read timeout")?;
+    stream.set_write_timeout(Some(timeouts.write))
+        .context("Failed to set write timeout")?;
+
+    Ok(stream)
+}
+
+fn send_daemon_request(mut stream: UnixStream, mut request: Value) -> Result<Value> {
+    if ACCEPT_GZIP.load(Ordering::SeqCst) {
+        accept_gzip(&mut request);
+    }
+    mark_client_policy(&mut request);
+    record_frame("daemon", "send", &request);
+    let request_str = serde_json::to_string(&request)?;
+    writeln!(stream, "{}", request_str)?;
+
+    let mut reader = BufReader::new(stream);
+    let response = read_daemon_response(&mut reader)?;
+    record_frame("daemon", "recv", &response);
+
+    if let Some(error) = response.get("error") {
+        return Err(daemon_error(error));
+    }
+
+    take_result(response)
+}
+
+/// Turns a daemon error object into an error, structured when it carries a
+/// JSON-RPC code and tagged with the failure kind the daemon reported
+fn daemon_error(error: &Value) -> anyhow::Error {
+    let mut shown = error.clone();
+    let kind = shown.as_object_mut().and_then(|e| e.remove("kind"));
+
+    let err = if shown.get("code").is_some() {
+        RpcError {
+            text: format!("Daemon error: {}", shown),
+            error: shown,
+        }
+        .into()
+    } else {
+        anyhow!("Daemon error: {}", shown)
+    };
+    match kind.as_ref().and_then(Value::as_str).and_then(Failure::from_name) {
+        Some(failure) => failure.wrap(err),
+        None => err,
+    }
+}
+
+/// Next JSON-RPC id for a request to a daemon; seeded by `--json-rpc-id-start`
+static NEXT_DAEMON_ID: AtomicU64 = AtomicU64::new(1);
+
+fn next_daemon_id() -> u64 {
+    NEXT_DAEMON_ID.fetch_add(1, Ordering::SeqCst)
+}
+
+/// Set by `--show-daemon-logs`: failed daemon requests carry the tail of `daemon.log`
+static SHOW_DAEMON_LOGS: AtomicBool = AtomicBool::new(false);
+
+/// Lines of `daemon.log` appended to an error with `--show-daemon-logs`
+const DAEMON_LOG_TAIL_LINES: usize = 10;
+
+/// Appends the last lines of the daemon's log to `error`, keeping its failure
+/// kind and JSON-RPC error, when `--show-daemon-logs` is on
+fn with_daemon_log_tail(server_name: &str, error: anyhow::Error) -> anyhow::Error {
+    if !SHOW_DAEMON_LOGS.load(Ordering::SeqCst) {
+        return error;
+    }
+    let path = DaemonManager::at(server_name, &server_profile_dir(server_name)).log_file();
+    let Ok(log) = fs::read_to_string(&path) else {
+        return error;
+    };
+    let lines: Vec<&str> = log.lines().collect();
+    let tail = &lines[lines.len().saturating_sub(DAEMON_LOG_TAIL_LINES)..];
+    if tail.is_empty() {
+        return error;
+    }
+
+    let text = format!("{:#}\n\nLast lines of {}:\n  {}", error, path.display(), tail.join("\n  "));
+    let rebuilt = match rpc_error(&error) {
+        Some(rpc) => RpcError { error: rpc.error.clone(), text }.into(),
+        None => anyhow!(text),
+    };
+    match Failure::of(&error) {
+        Some(failure) => failure.wrap(rebuilt),
+        None => rebuilt,
+    }
+}
+
+/// Sends one request to the daemon and returns its `result`
+fn request_via_daemon(server_name: &str, method: &str, params: Value) -> Result<Value> {
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": next_daemon_id(),
+        "method": method,
+        "params": params
+    });
+
+    connect_to_daemon(server_name)
+        .and_then(|stream| send_daemon_request(stream, request))
+        .map_err(|e| with_daemon_log_tail(server_name, e))
+}
+
+fn call_via_daemon(server_name: &str, tool: &str, args: Value) -> Result<Value> {
+    request_via_daemon(
+        server_name,
+        "tools/call",
+        json!({
+            "name": tool,
+            "arguments": args
+        }),
+    )
+}
+
+/// Sends a batch of tool calls to the daemon in one message; results are in call order
+fn batch_via_daemon(server_name: &str, calls: &[(String, Value)]) -> Result<Vec<Result<Value>>> {
+    let mut stream = connect_to_daemon(server_name)?;
+
+    let requests: Vec<Value> = calls
+        .iter()
+        .map(|(name, args)| {
+            json!({
+                "jsonrpc": "2.0",
+                "id": next_daemon_id(),
+                "method": "tools/call",
+                "params": {
+                    "name": name,
+                    "arguments": args
+                }
+            })
+        })
+        .collect();
+
+    let mut request = json!(requests);
+    if ACCEPT_GZIP.load(Ordering::SeqCst) {
+        accept_gzip(&mut request);
+    }
+    mark_client_policy(&mut request);
+    record_frame("daemon", "send", &request);
+    writeln!(stream, "{}", serde_json::to_string(&request)?)?;
+
+    let mut reader = BufReader::new(stream);
+    let response = read_daemon_response(&mut reader)?;
+    record_frame("daemon", "recv", &response);
+    let responses = response.as_array()
+        .ok_or_else(|| anyhow!("Daemon error: {}", response.get("error").unwrap_or(&response)))?;
+
+    Ok(requests
+        .iter()
+        .map(|request| {
+            let response = responses.iter()
+                .find(|r| ids_match(&r["id"], &request["id"]))
+                .ok_or_else(|| anyhow!("No response for batched call"))?;
+            match response.get("error") {
+                Some(error) => Err(daemon_error(error)),
+                None => take_result(response.clone()),
+            }
+        })
+        .collect())
+}
+
+fn list_tools_via_daemon(server_name: &str) -> Result<Value> {
+    request_via_daemon(server_name, "tools/list", json!({}))
+}
+
+/// Where `tui` and `call --args-stdin-jsonl` send requests: a running daemon, or a
+/// server started for this invocation
+enum ToolSource {
+    Daemon(String),
+    /// A server started for this run, with the profile whose `tool_defaults` and tool
+    /// policy it applies (with `--only-tool` / `--read-only`), as a daemon would
+    Direct(Box<McpClient>, Box<ServerProfile>),
+}
+
+impl ToolSource {
+    /// The server's daemon when one is running, otherwise a freshly started server,
+    /// unless `no_fallback` (or the profile's) makes that an error. With `verbose`,
+    /// reports which path was taken
+    fn open(
+        server_name: &str,
+        profile: &ServerProfile,
+        extra_args: Option<Vec<String>>,
+        options: &ClientOptions,
+        no_fallback: bool,
+        verbose: bool,
+    ) -> Result<Self> {
+        if DaemonManager::new(server_name).is_running().unwrap_or(false) {
+            if verbose {
+                eprintln!("via daemon");
+            }
+            return Ok(ToolSource::Daemon(server_name.to_string()));
+        }
+        if no_fallback || profile.no_fallback {
+            return Err(daemon_not_running_error(server_name));
+        }
+        if verbose {
+            eprintln!("started server directly");
+        }
+        run_hook("pre_start", &profile.pre_start, profile, server_name)?;
+        let mcp = McpClient::start(profile, extra_args, server_name, options)?;
+        let mut profile = profile.clone();
+        apply_policy_overrides(&mut profile, &options.only_tools, options.read_only);
+        Ok(ToolSource::Direct(Box::new(mcp), Box::new(profile)))
+    }
+
+    fn list_tools(&mut self) -> Result<Value> {
+        match self {
+            ToolSource::Daemon(server_name) => list_tools_via_daemon(server_name),
+            ToolSource::Direct(mcp, profile) => mcp.list_tools().map(|mut result| {
+                if let Some(tools) = result["tools"].as_array_mut() {
+                    tools.retain(|tool| {
+                        tool["name"].as_str().is_some_and(|name| check_tool_allowed(&profile.allowed_tools, name).is_ok())
+                    });
+                }
+                result
+            }),
+        }
+    }
+
+    fn call_tool(&mut self, tool: &str, args: Value) -> Result<Value> {
+        match self {
+            ToolSource::Daemon(server_name) => call_via_daemon(server_name, tool, args),
+            ToolSource::Direct(mcp, profile) => {
+                let args = checked_tool_args(profile, tool, args)?;
+                mcp.call_tool(tool, args)
+            }
+        }
+    }
+}
+
+/// Parses `prompt:<name>` or `resource:<uri template>` into a completion reference
+fn parse_completion_ref(reference: &str) -> Result<Value> {
+    match reference.split_once(':') {
+        Some(("prompt", name)) => Ok(json!({"type": "ref/prompt", "name": name})),
+        Some(("resource", uri)) => Ok(json!({"type": "ref/resource", "uri": uri})),
+        _ => Err(anyhow!(
+            "Invalid reference '{}': expected prompt:<name> or resource:<uri>",
+            reference
+        )),
+    }
+}
+
+/// Requests completions for `<ref> <name>=<partial>` and returns the candidates
+fn complete_via_daemon(server_name: &str, reference: &str, argument: &str) -> Result<Vec<String>> {
+    let reference = parse_completion_ref(reference)?;
+    let (name, partial) = argument.split_once('=').unwrap_or((argument, ""));
+
+    let result = request_via_daemon(
+        server_name,
+        "completion/complete",
+        json!({
+            "ref": reference,
+            "argument": {"name": name, "value": partial}
+        }),
+    )?;
+
+    let completion = &result["completion"];
+    if completion["hasMore"].as_bool().unwrap_or(false) {
+        match completion["total"].as_u64() {
+            Some(total) => eprintln!("(more results available, {} total)", total),
+            None => eprintln!("(more results available)"),
+        }
+    }
+    Ok(completion["values"]
+        .as_array()
+        .map(|v| v.iter().filter_map(|v| v.as_str().map(String::from)).collect())
+        .unwrap_or_default())
+}
+
+// ============================================================================
+// Repeated Calls
+// ============================================================================
+
+/// Returns the latency at percentile `p` (0.0..=1.0) from sorted samples
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Calls a tool through the daemon, retrying tool-level errors (`isError`) up to
+/// `retries` times with exponential backoff. Anything else (timeouts, protocol
+/// errors, refusals) fails at once. With `verbose`, reports each attempt
+fn call_with_retries(server_name: &str, tool: &str, args: &Value, retries: u32, verbose: bool) -> Result<Value> {
+    let mut delay = Duration::from_millis(200);
+    let mut attempt = 1;
+    loop {
+        let result = call_via_daemon(server_name, tool, args.clone());
+        match &result {
+            Err(e) if attempt <= retries && Failure::of(e) == Some(Failure::Tool) => {
+                if verbose {
+                    eprintln!(
+                        "Attempt {} of {} failed with a tool error, retrying in {}ms",
+                        attempt,
+                        retries + 1,
+                        delay.as_millis()
+                    );
+                }
+                std::thread::sleep(delay);
+                delay = (delay * 2).min(Duration::from_secs(5));
+                attempt += 1;
+            }
+            _ => {
+                if verbose {
+                    eprintln!("Attempts: {}", attempt);
+                }
+                return result;
+            }
+        }
+    }
+}
+
+/// Settings for `call --repeat`
+struct RepeatOptions {
+    repeat: u32,
+    concurrency: u32,
+    quiet: bool,
+}
+
+/// Fires the same tool call `repeat` times through the daemon, `concurrency` at a time,
+/// then prints success/failure counts and latency percentiles to stderr
+fn run_repeated_call(
+    server_name: &str,
+    tool: &str,
+    args: &Value,
+    options: &RepeatOptions,
+    jsonl: bool,
+    color: bool,
+) -> Result<()> {
+    let RepeatOptions { repeat, concurrency, quiet } = *options;
+    let next = AtomicU32::new(0);
+    let samples = std::sync::Mutex::new(Vec::with_capacity(repeat as usize));
+    let failures = std::sync::atomic::AtomicU32::new(0);
+
+    std::thread::scope(|scope| {
+        for _ in 0..concurrency.min(repeat) {
+            scope.spawn(|| {
+                while next.fetch_add(1, std::sync::atomic::Ordering::SeqCst) < repeat {
+                    let started = std::time::Instant::now();
+                    let result = call_via_daemon(server_name, tool, args.clone());
+                    samples.lock().unwrap().push(started.elapsed());
+
+                    if result.is_err() {
+                        failures.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    }
+                    if quiet {
+                        continue;
+                    }
+                    if jsonl {
+                        let _ = print_jsonl_record(tool, &result);
+                    } else {
+                        match result.and_then(|r| render_json(&r, color)) {
+                            Ok(rendered) => println!("{}", rendered),
+                            Err(e) => eprintln!("Error: {}", e),
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    let mut samples = samples.into_inner().unwrap();
+    samples.sort();
+    let failed = failures.into_inner();
+
+    eprintln!(
+        "Completed {} calls: {} ok, {} failed",
+        repeat,
+        repeat - failed,
+        failed
+    );
+    eprintln!(
+        "Latency: p50 {:.1?}, p95 {:.1?}, max {:.1?}",
+        percentile(&samples, 0.50),
+        percentile(&samples, 0.95),
+        samples.last().copied().unwrap_or_default()
+    );
+
+    if failed > 0 {
+        return Err(anyhow!("{} of {} calls failed", failed, repeat));
+    }
+    Ok(())
+}
+
+/// Calls `tool` once per stdin line, each line one JSON arguments object, printing a
+/// JSON Lines record per non-blank line (bad lines included) tagged with its line number
+fn call_each_stdin_line(source: &mut ToolSource, tool: &str, fail_fast: bool) -> Result<()> {
+    let started = Instant::now();
+    let (mut lines, mut failed) = (0, 0);
+
+    for (index, line) in std::io::stdin().lock().lines().enumerate() {
+        let line = line.context("Failed to read stdin")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line_number = index + 1;
+        lines += 1;
+
+        let result = serde_json::from_str::<Value>(&line)
+            .map_err(|e| anyhow!("Invalid JSON args: {}", e))
+            .and_then(|args| match args {
+                Value::Object(_) => Ok(args),
+                _ => Err(anyhow!("Args must be a JSON object")),
+            })
+            .and_then(|args| source.call_tool(tool, args));
+        let mut record = jsonl_record(tool, &result);
+        record["index"] = json!(line_number);
+        let mut stdout = std::io::stdout().lock();
+        writeln!(stdout, "{}", record)?;
+        stdout.flush()?;
+
+        if result.is_err() {
+            failed += 1;
+            if fail_fast {
+                return Err(anyhow!("Stopped at line {}", line_number));
+            }
+        }
+    }
+
+    eprintln!(
+        "Completed {} lines in {:.1?}: {} ok, {} failed",
+        lines,
+        started.elapsed(),
+        lines - failed,
+        failed
+    );
+    if failed > 0 {
+        return Err(anyhow!("{} of {} lines failed", failed, lines));
+    }
+    Ok(())
+}
+
+// ============================================================================
+// Watch Mode
+// ============================================================================
+
+/// Reruns a tool call through the daemon each time `path` changes,
+/// clearing the screen and reprinting the result. Rapid events are debounced.
+fn watch_call(server_name: &str, tool: &str, args: &Value, path: &Path, color: bool) -> Result<()> {
+    use notify::{EventKind, RecursiveMode, Watcher};
+
+    const DEBOUNCE: Duration = Duration::from_millis(200);
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).context("Failed to create file watcher")?;
+    watcher
+        .watch(path, RecursiveMode::Recursive)
+        .with_context(|| format!("Failed to watch {}", path.display()))?;
+
+    let clear_screen = std::io::stdout().is_terminal();
+
+    loop {
+        if clear_screen {
+            print!("\x1b[2J\x1b[H");
+        }
+        eprintln!("Watching {} (Ctrl-C to stop)", path.display());
+        match call_via_daemon(server_name, tool, args.clone()) {
+            Ok(result) => println!("{}", render_json(&result, color)?),
+            Err(e) => eprintln!("Error: {}", e),
+        }
+        std::io::stdout().flush()?;
+
+        // Block until a change (ignoring pure access events), then drain the burst
+        loop {
+            match rx.recv() {
+                Ok(Ok(event)) if !matches!(event.kind, EventKind::Access(_)) => break,
+                Ok(Ok(_)) => continue,
+                Ok(Err(e)) => eprintln!("Watch error: {}", e),
+                Err(_) => return Ok(()),
+            }
+        }
+        while rx.recv_timeout(DEBOUNCE).is_ok() {}
+    }
+}
+
+// ============================================================================
+// Attach
+// ============================================================================
+
+/// Follows `daemon.log` until Ctrl-C or the daemon exits. On a terminal the
+/// screen is redrawn with a status header above the latest log lines;
+/// otherwise new log lines are streamed as they arrive.
+fn attach(daemon_mgr: &DaemonManager, color: bool) -> Result<()> {
+    const LOG_LINES: usize = 20;
+
+    let log_path = daemon_mgr.log_file();
+    let mut log = fs::File::open(&log_path)
+        .with_context(|| format!("Failed to open {}", log_path.display()))?;
+    let tty = std::io::stdout().is_terminal();
+
+    let mut pending = String::new();
+    let mut recent: std::collections::VecDeque<String> = std::collections::VecDeque::new();
+    if tty {
+        log.read_to_string(&mut pending)?;
+    } else {
+        std::io::Seek::seek(&mut log, std::io::SeekFrom::End(0))?;
+        eprintln!("Attached to {} (Ctrl-C to detach)", daemon_mgr.server_name);
+    }
+
+    stop_on_interrupt();
+    let mut last_draw: Option<std::time::Instant> = None;
+    while !INTERRUPTED.load(Ordering::SeqCst) {
+        log.read_to_string(&mut pending)?;
+        let mut new_lines = false;
+        while let Some(end) = pending.find('\n') {
+            let line: String = pending.drain(..=end).collect();
+            let line = line.trim_end().to_string();
+            if tty {
+                recent.push_back(line);
+                if recent.len() > LOG_LINES {
+                    recent.pop_front();
+                }
+            } else {
+                println!("{}", line);
+            }
+            new_lines = true;
+        }
+
+        let running = daemon_mgr.is_running().unwrap_or(false);
+        if tty && (new_lines || !running || last_draw.is_none_or(|t| t.elapsed() >= Duration::from_secs(1))) {
+            let mut screen = String::from("\x1b[2J\x1b[H");
+            let title = format!("mcp-valve attach: {}", daemon_mgr.server_name);
+            screen.push_str(&format!("{}  (Ctrl-C to detach)\n", paint(&title, COLOR_BOLD, color)));
+            let status = if running {
+                daemon_mgr.stats_summary().unwrap_or_else(|| "Running".to_string())
+            } else {
+                "Daemon stopped".to_string()
+            };
+            screen.push_str(&format!("{}\n{}\n", status, "-".repeat(60)));
+            for line in &recent {
+                screen.push_str(line);
+                screen.push('\n');
+            }
+            print!("{}", screen);
+            last_draw = Some(std::time::Instant::now());
+        }
+        std::io::stdout().flush()?;
+
+        if !running {
+            eprintln!("Daemon for '{}' is no longer running", daemon_mgr.server_name);
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(250));
+    }
+    Ok(())
+}
+
+/// Lines shown by `logs` when neither `--lines` nor `--since` is given
+const DEFAULT_LOG_LINES: usize = 50;
+
+/// The time a log line was written: a JSON line's `timestamp` field or a
+/// leading `[<RFC 3339 timestamp>]`, as written by the daemon and `--log-file`
+fn log_line_time(line: &str) -> Option<SystemTime> {
+    let stamp = if line.starts_with('{') {
+        serde_json::from_str::<Value>(line).ok()?["timestamp"].as_str()?.to_string()
+    } else {
+        line.strip_prefix('[')?.split_once(']')?.0.to_string()
+    };
+    parse_rfc3339(&stamp)
+}
+
+/// Parses the UTC timestamps `timestamp_rfc3339` writes (`2025-06-18T12:34:56.789Z`)
+fn parse_rfc3339(stamp: &str) -> Option<SystemTime> {
+    let (date, time) = stamp.strip_suffix('Z')?.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let (hms, millis) = time.split_once('.').unwrap_or((time, "0"));
+    let mut hms = hms.splitn(3, ':').map(str::parse::<u64>);
+    let (hour, minute, second) = (hms.next()?.ok()?, hms.next()?.ok()?, hms.next()?.ok()?);
+    let millis: u64 = format!("{:0<3}", millis).get(..3)?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    // Days since epoch from the civil date (inverse of `timestamp_rfc3339`)
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = u64::try_from(era * 146_097 + doe - 719_468).ok()?;
+
+    let secs = days * 86_400 + hour * 3600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_millis(millis))
+}
+
+/// Prints the last `lines` lines of `path` logged within `since`. Untimestamped
+/// lines (server stderr, continuations) belong to the nearest timestamp above
+/// them; a file without any timestamps falls back to a plain tail.
+fn print_log_tail(path: &Path, lines: Option<usize>, since: Option<Duration>) -> Result<()> {
+    let file = fs::File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let cutoff = since.map(|age| SystemTime::now().checked_sub(age).unwrap_or(UNIX_EPOCH));
+    let limit = lines.unwrap_or(if cutoff.is_some() { usize::MAX } else { DEFAULT_LOG_LINES });
+
+    let mut kept = std::collections::VecDeque::new();
+    // Lines before the first timestamp, kept only if that timestamp is within the window
+    let mut leading = Vec::new();
+    let mut current: Option<bool> = None;
+    for line in BufReader::new(file).lines() {
+        let line = line.with_context(|| format!("Failed to read {}", path.display()))?;
+        if let Some(cutoff) = cutoff {
+            if let Some(time) = log_line_time(&line) {
+                let recent = time >= cutoff;
+                if current.is_none() && recent {
+                    kept.extend(leading.drain(..));
+                }
+                current = Some(recent);
+            }
+            match current {
+                None => {
+                    leading.push(line);
+                    continue;
+                }
+                Some(false) => continue,
+                Some(true) => {}
+            }
+        }
+        kept.push_back(line);
+        while kept.len() > limit {
+            kept.pop_front();
+        }
+    }
+
+    if cutoff.is_some() && current.is_none() {
+        eprintln!("{} has no timestamps; showing the last {} lines instead", path.display(), lines.unwrap_or(DEFAULT_LOG_LINES));
+        let skip = leading.len().saturating_sub(lines.unwrap_or(DEFAULT_LOG_LINES));
+        kept.extend(leading.drain(..).skip(skip));
+    }
+
+    let mut stdout = std::io::stdout().lock();
+    for line in kept {
+        writeln!(stdout, "{}", line)?;
+    }
+    Ok(())
+}
+
+// ============================================================================
+// Tools Diff
+// ============================================================================
 
-        // Wait for socket file to appear
-        for i in 0..50 {
-            if expected_socket.exists() {
-                eprintln!("Daemon started (PID: {})", child_pid);
-                eprintln!("Socket: {}", expected_socket.display());
-                return Ok(());
-            }
-            std::thread::sleep(Duration::from_millis(100));
+/// Tools keyed by name from a `tools/list` result (or a bare array of tools)
+fn tools_by_name(tools: &Value) -> std::collections::BTreeMap<String, Value> {
+    let list = tools.get("tools").unwrap_or(tools);
+    list.as_array()
+        .map(|t| t.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|t| Some((t["name"].as_str()?.to_string(), t.clone())))
+        .collect()
+}
 
-            // After 2 seconds, check if process is still alive
-            if i == 20 {
-                // Use kill with signal 0 to check if process exists
-                if kill(Pid::from_raw(child_pid as i32), None).is_err() {
-                    fs::remove_file(&self.pid_file).ok();
-                    return Err(anyhow!(
-                        "Daemon process exited unexpectedly. Check {}/daemon.log",
-                        profile_dir.display()
-                    ));
+/// Collects `path: old -> new` lines for every difference between two values
+fn diff_json(path: &str, old: &Value, new: &Value, out: &mut Vec<String>) {
+    match (old, new) {
+        (Value::Object(a), Value::Object(b)) => {
+            let keys: std::collections::BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+            for key in keys {
+                let child = format!("{}.{}", path, key);
+                match (a.get(key), b.get(key)) {
+                    (Some(x), Some(y)) => diff_json(&child, x, y, out),
+                    (Some(x), None) => out.push(format!("{}: removed (was {})", child, x)),
+                    (None, Some(y)) => out.push(format!("{}: added {}", child, y)),
+                    (None, None) => {}
                 }
             }
         }
+        _ if old != new => out.push(format!("{}: {} -> {}", path, old, new)),
+        _ => {}
+    }
+}
 
-        // Timeout
-        fs::remove_file(&self.pid_file).ok();
-        Err(anyhow!(
-            "Daemon failed to start - socket file not created within 5 seconds. Check {}/daemon.log",
-            profile_dir.display()
-        ))
+/// Prints added/removed/changed tools and returns how many differ
+fn print_tools_diff(old: &Value, new: &Value, color: bool) -> usize {
+    let old = tools_by_name(old);
+    let new = tools_by_name(new);
+    let mut changes = 0;
+
+    for name in old.keys().filter(|n| !new.contains_key(*n)) {
+        println!("{}", paint(&format!("- {}", name), COLOR_LITERAL, color));
+        changes += 1;
+    }
+    for name in new.keys().filter(|n| !old.contains_key(*n)) {
+        println!("{}", paint(&format!("+ {}", name), COLOR_STRING, color));
+        changes += 1;
+    }
+    for (name, tool) in &new {
+        let Some(previous) = old.get(name) else {
+            continue;
+        };
+        let mut lines = Vec::new();
+        diff_json("inputSchema", &previous["inputSchema"], &tool["inputSchema"], &mut lines);
+        if !lines.is_empty() {
+            println!("{}", paint(&format!("~ {}", name), COLOR_KEY, color));
+            for line in lines {
+                println!("    {}", line);
+            }
+            changes += 1;
+        }
     }
+    changes
+}
 
-    fn stop(&self) -> Result<()> {
-        if !self.is_running()? {
-            return Err(daemon_not_running_error(&self.server_name));
+// ============================================================================
+// Tools Index
+// ============================================================================
+
+/// The tools index lives next to the config file that defines the servers
+fn tools_index_path(config: &ServerConfig) -> PathBuf {
+    config.path.parent().unwrap_or(Path::new(".")).join("tools-index.json")
+}
+
+/// Starts every configured server directly and maps its name to its tools.
+/// Failures are reported and skipped.
+fn build_tools_index(
+    config: &ServerConfig,
+    options: &ClientOptions,
+    jobs: usize,
+) -> std::collections::BTreeMap<String, Value> {
+    let mut names: Vec<&String> = config.servers.keys().collect();
+    names.sort();
+
+    let results = run_parallel(&names, jobs, |name| {
+        let profile = &config.servers[*name];
+        run_hook("pre_start", &profile.pre_start, profile, name)
+            .and_then(|()| McpClient::start(profile, None, name, options))
+            .and_then(|mut mcp| mcp.list_tools())
+    });
+
+    let mut index = std::collections::BTreeMap::new();
+    for (name, tools) in names.into_iter().zip(results) {
+        match tools {
+            Ok(result) => {
+                index.insert(name.clone(), result["tools"].clone());
+            }
+            Err(e) => eprintln!("Skipping '{}': {}", name, e),
+        }
+    }
+    index
+}
+
+/// Runs `f` over `items` on up to `jobs` threads and returns the results in input
+/// order. Each item is handled start to finish on one thread, so an `McpClient`
+/// created inside `f` never leaves the thread that started it.
+fn run_parallel<T: Sync, R: Send>(items: &[T], jobs: usize, f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let next = AtomicUsize::new(0);
+    let results = std::sync::Mutex::new((0..items.len()).map(|_| None).collect::<Vec<Option<R>>>());
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let Some(item) = items.get(i) else { break };
+                let result = f(item);
+                results.lock().unwrap()[i] = Some(result);
+            });
         }
+    });
 
-        let project = get_project_path();
-        let pid_str = fs::read_to_string(&self.pid_file)?;
-        let pid: i32 = pid_str.trim().parse()
-            .context("Invalid PID in file")?;
+    results.into_inner().unwrap().into_iter().flatten().collect()
+}
 
-        let socket_path = self.get_socket_path().ok();
+/// Worker threads for multi-server commands: `--jobs`, or the CPU count
+fn job_count(jobs: Option<std::num::NonZeroUsize>) -> usize {
+    jobs.or_else(|| std::thread::available_parallelism().ok())
+        .map_or(1, std::num::NonZeroUsize::get)
+}
 
-        eprintln!("Project: {}", project);
-        eprintln!("Stopping daemon (PID: {})...", pid);
+fn print_index_summary(index: &serde_json::Map<String, Value>, color: bool) {
+    let mut total = 0;
+    for (server, tools) in index {
+        let count = tools.as_array().map_or(0, Vec::len);
+        total += count;
+        println!("  {}: {} tools", paint(server, COLOR_KEY, color), count);
+    }
+    println!("{} tools from {} servers", total, index.len());
+}
 
-        // Send SIGTERM
-        kill(Pid::from_raw(pid), Signal::SIGTERM)
-            .context("Failed to send SIGTERM")?;
+/// Case-insensitive substring match on a tool's name or description
+fn tool_matches(tool: &Value, query: &str) -> bool {
+    let query = query.to_lowercase();
+    ["name", "description"]
+        .iter()
+        .filter_map(|field| tool[field].as_str())
+        .any(|text| text.to_lowercase().contains(&query))
+}
 
-        // Wait for graceful shutdown
-        for _ in 0..10 {
-            if !self.is_running()? {
-                fs::remove_file(&self.pid_file).ok();
-                if let Some(ref sp) = socket_path {
-                    if sp.exists() {
-                        fs::remove_file(sp).ok();
+// ============================================================================
+// Recipes
+// ============================================================================
+
+/// A `run` recipe: steps executed in order, stopping at the first failure
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Recipe {
+    steps: Vec<RecipeStep>,
+}
+
+/// One recipe step: a tool call through `server`'s daemon, or starting it
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RecipeStep {
+    server: String,
+    /// Tool to call
+    #[serde(default)]
+    call: Option<String>,
+    /// Tool arguments; `${var}` in strings is expanded
+    #[serde(default)]
+    args: Option<Value>,
+    /// Variables set from the call's result, as name -> path (`$.token`, `$.items[0].id`)
+    #[serde(default)]
+    bind: std::collections::BTreeMap<String, String>,
+    /// Start the server's daemon instead of calling a tool
+    #[serde(default)]
+    start_daemon: bool,
+    /// Environment overrides for `start_daemon`; `${var}` is expanded
+    #[serde(default)]
+    env: HashMap<String, String>,
+}
+
+/// Expands `${name}` with recipe variables first, then the environment
+fn expand_recipe_str(text: &str, vars: &HashMap<String, String>) -> String {
+    expand_refs(text, |name| vars.get(name).cloned().or_else(|| std::env::var(name).ok()))
+}
+
+/// Expands `${name}` in every string of `value`, see `expand_recipe_str`
+fn expand_recipe_vars(value: &Value, vars: &HashMap<String, String>) -> Value {
+    match value {
+        Value::String(text) => Value::String(expand_recipe_str(text, vars)),
+        Value::Array(items) => Value::Array(items.iter().map(|v| expand_recipe_vars(v, vars)).collect()),
+        Value::Object(map) => Value::Object(
+            map.iter().map(|(k, v)| (k.clone(), expand_recipe_vars(v, vars))).collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// Runs a recipe's steps in order. Call results are printed; bound values come
+/// from the same value `$last` would (structuredContent, parsed text, or result).
+fn run_recipe(
+    recipe: &Recipe,
+    config: &ServerConfig,
+    client_options: &ClientOptions,
+    output: &OutputOptions,
+) -> Result<()> {
+    let mut vars: HashMap<String, String> = HashMap::new();
+
+    for (i, step) in recipe.steps.iter().enumerate() {
+        let context = || format!("Step {} ({})", i + 1, step.server);
+        let (server_name, profile) = config.resolve(&step.server).with_context(context)?;
+
+        match (&step.call, step.start_daemon) {
+            (Some(tool), false) => {
+                eprintln!("Step {}: call {} on {}", i + 1, tool, server_name);
+                if !DaemonManager::new(&server_name).is_running().unwrap_or(false) {
+                    return Err(daemon_not_running_error(&server_name)).with_context(context);
+                }
+                let args = expand_recipe_vars(step.args.as_ref().unwrap_or(&json!({})), &vars);
+                let result = call_via_daemon(&server_name, tool, args).with_context(context)?;
+                print_result(&result, output)?;
+
+                let value = last_value(result);
+                for (name, path) in &step.bind {
+                    let rest = path.strip_prefix('$')
+                        .ok_or_else(|| anyhow!("Binding '{}' must start with '$': {}", name, path))
+                        .with_context(context)?;
+                    let (bound, rest) = walk_path(&value, rest, "$").with_context(context)?;
+                    if !rest.is_empty() {
+                        return Err(anyhow!("Invalid path for binding '{}': {}", name, path)).with_context(context);
                     }
+                    let bound = match bound {
+                        Value::String(text) => text.clone(),
+                        other => other.to_string(),
+                    };
+                    vars.insert(name.clone(), bound);
                 }
-                eprintln!("Daemon stopped");
-                return Ok(());
             }
-            std::thread::sleep(Duration::from_millis(500));
+            (None, true) => {
+                eprintln!("Step {}: start daemon for {}", i + 1, server_name);
+                let env_overrides = step.env
+                    .iter()
+                    .map(|(key, value)| (key.clone(), expand_recipe_str(value, &vars)))
+                    .collect();
+                let launch = DaemonLaunch {
+                    config: config.selection.clone(),
+                    server_args: None,
+                    env: env_overrides,
+                    options: client_options.clone(),
+                };
+                DaemonManager::new(&server_name)
+                    .start(profile, &launch)
+                    .with_context(context)?;
+            }
+            _ => return Err(anyhow!("Each step needs exactly one of \"call\" or \"start_daemon\"")).with_context(context),
         }
+    }
+    Ok(())
+}
 
-        // Force kill
-        kill(Pid::from_raw(pid), Signal::SIGKILL)
-            .context("Failed to send SIGKILL")?;
+// ============================================================================
+// Output
+// ============================================================================
 
-        fs::remove_file(&self.pid_file).ok();
-        if let Some(ref sp) = socket_path {
-            if sp.exists() {
-                fs::remove_file(sp).ok();
+const COLOR_RESET: &str = "\x1b[0m";
+const COLOR_KEY: &str = "\x1b[1;34m";
+const COLOR_STRING: &str = "\x1b[32m";
+const COLOR_NUMBER: &str = "\x1b[36m";
+const COLOR_LITERAL: &str = "\x1b[35m";
+const COLOR_BOLD: &str = "\x1b[1m";
+
+const COLOR_ERROR: &str = "\x1b[1;31m";
+const COLOR_WARNING: &str = "\x1b[1;33m";
+
+/// Set once the CLI is parsed: whether `Error:` / `Warning:` labels on stderr are colored
+static STDERR_COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Whether to color a stream. An explicit `--color always|never` wins; under `auto`,
+/// a non-empty NO_COLOR disables color, CLICOLOR_FORCE (not `0`) forces it, CLICOLOR=0
+/// disables it, and otherwise only terminals are colored.
+fn use_color(choice: ColorChoice, terminal: bool) -> bool {
+    let env = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto if env("NO_COLOR").is_some() => false,
+        ColorChoice::Auto if env("CLICOLOR_FORCE").is_some_and(|v| v != "0") => true,
+        ColorChoice::Auto if env("CLICOLOR").as_deref() == Some("0") => false,
+        ColorChoice::Auto => terminal,
+    }
+}
+
+/// A stderr label such as `Error:`, colored when stderr color is on
+fn stderr_label(label: &str, color: &str) -> String {
+    paint(label, color, STDERR_COLOR.load(Ordering::SeqCst))
+}
+
+fn paint(text: &str, color: &str, enabled: bool) -> String {
+    if enabled {
+        format!("{}{}{}", color, text, COLOR_RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Writes `value` pretty-printed like `serde_json::to_string_pretty`, with ANSI colors
+fn write_colored_json(value: &Value, indent: usize, out: &mut String) {
+    let pad = |level: usize| "  ".repeat(level);
+
+    match value {
+        Value::Null | Value::Bool(_) => out.push_str(&paint(&value.to_string(), COLOR_LITERAL, true)),
+        Value::Number(_) => out.push_str(&paint(&value.to_string(), COLOR_NUMBER, true)),
+        Value::String(_) => out.push_str(&paint(&value.to_string(), COLOR_STRING, true)),
+        Value::Array(items) if items.is_empty() => out.push_str("[]"),
+        Value::Array(items) => {
+            out.push_str("[\n");
+            for (i, item) in items.iter().enumerate() {
+                out.push_str(&pad(indent + 1));
+                write_colored_json(item, indent + 1, out);
+                out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+            }
+            out.push_str(&pad(indent));
+            out.push(']');
+        }
+        Value::Object(map) if map.is_empty() => out.push_str("{}"),
+        Value::Object(map) => {
+            out.push_str("{\n");
+            for (i, (key, item)) in map.iter().enumerate() {
+                out.push_str(&pad(indent + 1));
+                out.push_str(&paint(&Value::from(key.as_str()).to_string(), COLOR_KEY, true));
+                out.push_str(": ");
+                write_colored_json(item, indent + 1, out);
+                out.push_str(if i + 1 < map.len() { ",\n" } else { "\n" });
             }
+            out.push_str(&pad(indent));
+            out.push('}');
         }
+    }
+}
 
-        eprintln!("Daemon stopped (forced)");
-        Ok(())
+/// Renders a result for display: pretty JSON, syntax-highlighted when `color` is set,
+/// or one compact line with `--compact`
+fn render_json(value: &Value, color: bool) -> Result<String> {
+    if COMPACT_JSON.load(Ordering::SeqCst) {
+        return Ok(serde_json::to_string(value)?);
     }
+    if color {
+        let mut out = String::new();
+        write_colored_json(value, 0, &mut out);
+        Ok(out)
+    } else {
+        Ok(serde_json::to_string_pretty(value)?)
+    }
+}
 
-    fn status(&self) -> Result<()> {
-        let project = get_project_path();
-        let profile_dir = self.pid_file.parent().unwrap();
-        println!("Project: {}", project);
-        println!("Server: {}", self.server_name);
-        println!("Profile: {}", profile_dir.display());
+/// Set by `--compact`: results are printed as single-line JSON
+static COMPACT_JSON: AtomicBool = AtomicBool::new(false);
+
+/// Where and how command results are printed
+struct OutputOptions {
+    color: bool,
+    /// Write results to this file instead of stdout
+    out: Option<PathBuf>,
+    /// `Some(true)` for `--pager`, `Some(false)` for `--no-pager`, `None` to page
+    /// only results taller than the terminal
+    pager: Option<bool>,
+}
 
-        if self.is_running()? {
-            let pid_str = fs::read_to_string(&self.pid_file)?;
-            let socket_path = self.get_socket_path()?;
-            println!("Daemon is running");
-            println!("  PID: {}", pid_str.trim());
-            println!("  Socket: {}", socket_path.display());
-        } else {
-            println!("Daemon is not running");
-            if self.pid_file.exists() {
-                eprintln!("Warning: Stale PID file found, cleaning up...");
-                let socket_path = self.get_socket_path().ok();
-                fs::remove_file(&self.pid_file).ok();
-                if let Some(sp) = socket_path {
-                    if sp.exists() {
-                        fs::remove_file(&sp).ok();
-                    }
+/// Rows of the terminal on stdout, if it is one
+fn terminal_rows() -> Option<usize> {
+    let mut size = nix::libc::winsize { ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0 };
+    // SAFETY: TIOCGWINSZ only writes into the winsize struct passed to it
+    let ret = unsafe { nix::libc::ioctl(nix::libc::STDOUT_FILENO, nix::libc::TIOCGWINSZ, &mut size) };
+    (ret == 0 && size.ws_row > 0).then_some(size.ws_row as usize)
+}
+
+/// Whether a rendered result should go through the pager; never when stdout
+/// is not a terminal
+fn wants_pager(text: &str, pager: Option<bool>) -> bool {
+    if pager == Some(false) || !std::io::stdout().is_terminal() {
+        return false;
+    }
+    pager == Some(true) || terminal_rows().is_some_and(|rows| text.lines().count() >= rows)
+}
+
+/// Pipes text through `$PAGER` (default `less -R`, which keeps colors)
+fn page_output(text: &str) -> Result<()> {
+    let pager = std::env::var("PAGER")
+        .ok()
+        .filter(|p| !p.trim().is_empty())
+        .unwrap_or_else(|| "less -R".to_string());
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&pager)
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to start pager: {}", pager))?;
+
+    // Quitting the pager early closes the pipe; that is not an error
+    let mut stdin = child.stdin.take().unwrap();
+    match writeln!(stdin, "{}", text) {
+        Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => return Err(e.into()),
+        _ => {}
+    }
+    drop(stdin);
+    child.wait().context("Failed to wait for pager")?;
+    Ok(())
+}
+
+/// Writes rendered output to a file (creating parent dirs) and confirms on stderr
+fn write_output_file(path: &Path, content: &str) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    fs::write(path, format!("{}\n", content))
+        .with_context(|| format!("Failed to write output: {}", path.display()))?;
+    eprintln!("Result written to {} ({} bytes)", path.display(), content.len() + 1);
+    Ok(())
+}
+
+/// Prints a single result to stdout, or to the `--out` file when set
+fn print_result(value: &Value, output: &OutputOptions) -> Result<()> {
+    match &output.out {
+        Some(path) => write_output_file(path, &render_json(value, false)?),
+        None => {
+            let rendered = render_json(value, output.color)?;
+            if wants_pager(&rendered, output.pager) {
+                return page_output(&rendered);
+            }
+            println!("{}", rendered);
+            Ok(())
+        }
+    }
+}
+
+/// Builds `call --positional` arguments from the tool's `inputSchema`. The schema's
+/// properties come back sorted by name, so declaration order is lost; the order used
+/// is the sole property, or else the `required` list (an array, so its order is the
+/// author's). Values beyond that have no stable target and are an error
+fn positional_arguments(server_name: &str, tool: &str, values: &[String]) -> Result<Value> {
+    let use_args = || format!("use --args '{{...}}' for '{}'", tool);
+    let tools = list_tools_via_daemon(server_name)
+        .with_context(|| format!("Cannot fetch the inputSchema for --positional; {}", use_args()))?;
+    let schema = tools["tools"].as_array()
+        .and_then(|tools| tools.iter().find(|t| t["name"] == tool))
+        .map(|t| &t["inputSchema"])
+        .ok_or_else(|| anyhow!("Tool '{}' not found on server '{}'", tool, server_name))?;
+    let properties = schema["properties"].as_object().cloned().unwrap_or_default();
+
+    let order: Vec<String> = if properties.len() == 1 {
+        properties.keys().cloned().collect()
+    } else {
+        schema["required"].as_array()
+            .map(|names| names.iter().filter_map(Value::as_str).filter(|n| properties.contains_key(*n)).map(String::from).collect())
+            .unwrap_or_default()
+    };
+    if values.len() > order.len() {
+        return Err(Failure::Config.wrap(anyhow!(
+            "{} values given for '{}', but only these of its properties have a known order: {}; {}",
+            values.len(),
+            tool,
+            if order.is_empty() { "none".to_string() } else { order.join(", ") },
+            use_args()
+        )));
+    }
+
+    let mut args = serde_json::Map::new();
+    for (name, value) in order.iter().zip(values) {
+        args.insert(name.clone(), coerce_to_schema(name, value, &properties[name])?);
+    }
+    Ok(Value::Object(args))
+}
+
+/// Adds `call --arg-file` (the path) and `--arg-file-contents` (the text) arguments
+/// to `args`, replacing same-named keys. Paths get template variables expanded and
+/// are made absolute, since the server may run in another directory
+fn add_file_arguments(args: &mut Value, by_path: &[String], by_contents: &[String], server_name: &str, profile: &ServerProfile) -> Result<()> {
+    if by_path.is_empty() && by_contents.is_empty() {
+        return Ok(());
+    }
+    let object = args.as_object_mut()
+        .ok_or_else(|| Failure::Config.wrap(anyhow!("--arg-file needs the arguments to be a JSON object")))?;
+    let cwd = server_working_dir(profile, server_name);
+    let parse = |flag: &str, pair: &str| -> Result<(String, PathBuf)> {
+        let (name, path) = pair
+            .split_once('=')
+            .and_then(|(name, path)| Some((name, path.strip_prefix('@')?)))
+            .filter(|(name, path)| !name.is_empty() && !path.is_empty())
+            .ok_or_else(|| Failure::Config.wrap(anyhow!("Invalid {} '{}': expected NAME=@PATH", flag, pair)))?;
+        let path = PathBuf::from(expand_template_vars(path, server_name, &cwd));
+        let path = std::path::absolute(&path).unwrap_or(path);
+        Ok((name.to_string(), path))
+    };
+
+    for pair in by_path {
+        let (name, path) = parse("--arg-file", pair)?;
+        let path = path.to_str()
+            .ok_or_else(|| Failure::Config.wrap(anyhow!("--arg-file path is not valid UTF-8: {}", path.display())))?;
+        object.insert(name, Value::String(path.to_string()));
+    }
+    for pair in by_contents {
+        let (name, path) = parse("--arg-file-contents", pair)?;
+        let contents = fs::read(&path)
+            .map_err(|e| Failure::Config.wrap(anyhow!("Cannot read {} for --arg-file-contents: {}", path.display(), e)))?;
+        let contents = String::from_utf8(contents)
+            .map_err(|_| Failure::Config.wrap(anyhow!("{} is not UTF-8 text; pass it with --arg-file instead", path.display())))?;
+        object.insert(name, Value::String(contents));
+    }
+    Ok(())
+}
+
+/// Converts a command-line value to the property's declared `type`; without one,
+/// JSON if it parses, otherwise a string
+fn coerce_to_schema(name: &str, value: &str, property: &Value) -> Result<Value> {
+    let kind = property["type"].as_str().unwrap_or_default();
+    let parsed = match kind {
+        "string" => return Ok(Value::String(value.to_string())),
+        "" => return Ok(serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()))),
+        "integer" => value.parse::<i64>().ok().map(Value::from),
+        "number" => serde_json::from_str(value).ok().filter(Value::is_number),
+        "boolean" => value.parse::<bool>().ok().map(Value::Bool),
+        "array" => serde_json::from_str(value).ok().filter(Value::is_array),
+        "object" => serde_json::from_str(value).ok().filter(Value::is_object),
+        "null" => (value == "null").then_some(Value::Null),
+        _ => serde_json::from_str(value).ok(),
+    };
+    parsed.ok_or_else(|| Failure::Config.wrap(anyhow!("Argument '{}' must be {}, got '{}'", name, kind, value)))
+}
+
+/// Picks the part of `value` that `--select` names: a JSON pointer (`/a/0`) or
+/// `.a[0].b`, optionally rooted at `$`
+fn select_path<'a>(value: &'a Value, path: &str) -> Result<&'a Value> {
+    if path.starts_with('/') {
+        return value.pointer(path)
+            .ok_or_else(|| anyhow!("--select {} matched nothing in the result", path));
+    }
+    let rest = path.strip_prefix('$').unwrap_or(path);
+    let (selected, rest) = walk_path(value, rest, "result")
+        .with_context(|| format!("--select {} matched nothing in the result", path))?;
+    if !rest.is_empty() {
+        return Err(anyhow!("Invalid --select path '{}': unexpected '{}'", path, rest));
+    }
+    Ok(selected)
+}
+
+/// Fills `{{.path}}` placeholders (`.a.0.b`, `.a[0].b`, or `.` for the whole result)
+/// from `result`: strings raw, `null` empty, anything else as compact JSON. A path
+/// that matches nothing renders empty, or fails with `strict`
+fn render_output_template(template: &str, result: &Value, strict: bool) -> Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..].find("}}")
+            .ok_or_else(|| anyhow!("Unclosed '{{{{' in --output-template"))?;
+        let path = rest[start + 2..start + end].trim();
+        let field = match path {
+            "." => Ok(result),
+            _ if path.starts_with('.') || path.starts_with('[') => match walk_path(result, path, "result") {
+                Ok((value, "")) => Ok(value),
+                Ok((_, extra)) => return Err(anyhow!("Invalid placeholder '{{{{{}}}}}': unexpected '{}'", path, extra)),
+                Err(e) => Err(e),
+            },
+            _ => return Err(anyhow!("Invalid placeholder '{{{{{}}}}}': paths start with '.'", path)),
+        };
+        match field {
+            Ok(Value::String(text)) => rendered.push_str(text),
+            Ok(Value::Null) => {}
+            Ok(value) => rendered.push_str(&value.to_string()),
+            Err(e) if strict => return Err(e.context(format!("--output-template placeholder {{{{{}}}}} matched nothing", path))),
+            Err(_) => {}
+        }
+        rest = &rest[start + end + 2..];
+    }
+
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// Prints a `--select`ed value: strings raw, anything else as JSON
+fn print_selected(value: &Value, output: &OutputOptions) -> Result<()> {
+    let Value::String(text) = value else {
+        return print_result(value, output);
+    };
+    match &output.out {
+        Some(path) => write_output_file(path, text),
+        None => {
+            println!("{}", text);
+            Ok(())
+        }
+    }
+}
+
+/// Builds the JSON Lines record for a tool call, tagged with tool name and status
+fn jsonl_record(tool: &str, result: &Result<Value>) -> Value {
+    match result {
+        Ok(value) => json!({"tool": tool, "ok": true, "result": value}),
+        Err(e) => json!({"tool": tool, "ok": false, "error": e.to_string()}),
+    }
+}
+
+/// Prints one compact JSON Lines record for a tool call and flushes stdout
+/// so downstream consumers see results as they complete
+fn print_jsonl_record(tool: &str, result: &Result<Value>) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    writeln!(stdout, "{}", jsonl_record(tool, result))?;
+    stdout.flush()?;
+    Ok(())
+}
+
+// ============================================================================
+// Shell Helpers
+// ============================================================================
+
+/// Splits `<tool> [json_args]` into the tool name and its unparsed args (`{}` if absent)
+fn split_call_line(line: &str) -> (&str, &str) {
+    let line = line.trim();
+    match line.split_once(char::is_whitespace) {
+        Some((tool, args)) => (tool, args.trim()),
+        None => (line, "{}"),
+    }
+}
+
+/// Runs `<tool> [json_args]` lines from stdin against the daemon, like the shell's
+/// `call` (including `$last`), printing one JSON Lines record per call
+fn stream_calls(server_name: &str, fail_fast: bool) -> Result<()> {
+    let mut last: Option<Value> = None;
+    let (mut lines, mut failed) = (0, 0);
+
+    for (index, line) in std::io::stdin().lock().lines().enumerate() {
+        let line = line.context("Failed to read stdin")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line_number = index + 1;
+        lines += 1;
+
+        let (tool, args) = split_call_line(&line);
+        let args = substitute_last(args, last.as_ref()).and_then(|args| {
+            serde_json::from_str::<Value>(&args).context("Invalid JSON args")
+        });
+        let args = match args {
+            Ok(args) => args,
+            Err(e) => {
+                failed += 1;
+                eprintln!("Line {}: {:#}", line_number, e);
+                if fail_fast {
+                    return Err(anyhow!("Stopped at line {}", line_number));
                 }
+                continue;
             }
+        };
+
+        let result = call_via_daemon(server_name, tool, args);
+        print_jsonl_record(tool, &result)?;
+        match result {
+            Ok(result) => last = Some(last_value(result)),
+            Err(_) if fail_fast => return Err(anyhow!("Stopped at line {}", line_number)),
+            Err(_) => failed += 1,
         }
-        Ok(())
     }
+
+    if failed > 0 {
+        return Err(anyhow!("{} of {} lines failed", failed, lines));
+    }
+    Ok(())
+}
+
+/// Byte offset of the first `needle` that is not inside a JSON string literal
+fn find_outside_strings(input: &str, needle: &str) -> Option<usize> {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in input.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if input[i..].starts_with(needle) {
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// Splits a trailing `| jq '<filter>'` off a shell line
+fn split_jq_pipe(input: &str) -> (&str, Option<String>) {
+    let Some(pos) = find_outside_strings(input, "|") else {
+        return (input, None);
+    };
+    let Some(filter) = input[pos + 1..].trim().strip_prefix("jq") else {
+        return (input, None);
+    };
+    let filter = filter.trim();
+    let filter = ['\'', '"']
+        .iter()
+        .find_map(|&q| filter.strip_prefix(q).and_then(|f| f.strip_suffix(q)))
+        .unwrap_or(filter);
+    let filter = if filter.is_empty() { "." } else { filter };
+    (input[..pos].trim_end(), Some(filter.to_string()))
+}
+
+/// Replaces `$last`, `$last.field` and `$last.items[0].id` outside string
+/// literals with the JSON of that part of the previous result
+fn substitute_last(args: &str, last: Option<&Value>) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = args;
+    while let Some(pos) = find_outside_strings(rest, "$last") {
+        let last = last.ok_or_else(|| anyhow!("$last is not set yet"))?;
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos + "$last".len()..];
+
+        let (value, after) = walk_path(last, rest, "$last")?;
+        rest = after;
+        out.push_str(&serde_json::to_string(value)?);
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Follows the `.field` and `[index]` accessors at the start of `path` from
+/// `value`, returning the selected value and the rest of `path`. `name` is the
+/// root as the user wrote it (`$last`, `$`), for error messages.
+fn walk_path<'a, 'p>(mut value: &'a Value, mut path: &'p str, name: &str) -> Result<(&'a Value, &'p str)> {
+    loop {
+        if let Some(after) = path.strip_prefix('.') {
+            let end = after
+                .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+                .unwrap_or(after.len());
+            let key = &after[..end];
+            // `.0` indexes an array, like `[0]`
+            let field = match (value, key.parse::<usize>()) {
+                (Value::Array(items), Ok(index)) => items.get(index),
+                _ => value.get(key),
+            };
+            value = field.ok_or_else(|| anyhow!("{} has no field '{}'", name, key))?;
+            path = &after[end..];
+        } else if let Some(after) = path.strip_prefix('[') {
+            let end = after.find(']')
+                .ok_or_else(|| anyhow!("Unclosed '[' after {}", name))?;
+            let index: usize = after[..end].trim().parse()
+                .with_context(|| format!("Invalid index '{}' after {}", &after[..end], name))?;
+            value = value.get(index)
+                .ok_or_else(|| anyhow!("{} has no index {}", name, index))?;
+            path = &after[end + 1..];
+        } else {
+            return Ok((value, path));
+        }
+    }
+}
+
+/// The value `$last` refers to: a tool's `structuredContent`, or its single
+/// text block when that parses as JSON, otherwise the whole result
+fn last_value(result: Value) -> Value {
+    if let Some(structured) = result.get("structuredContent") {
+        return structured.clone();
+    }
+    if let Some([block]) = result.get("content").and_then(|c| c.as_array()).map(|c| c.as_slice()) {
+        if let Some(parsed) = block["text"].as_str().and_then(|t| serde_json::from_str(t).ok()) {
+            return parsed;
+        }
+    }
+    result
+}
+
+/// Prints one line per tool: name and the first line of its description
+fn print_tool_summary(tools: &Value, color: bool) {
+    const MAX_DESCRIPTION: usize = 72;
+
+    let tools = tools["tools"].as_array().map(|t| t.as_slice()).unwrap_or_default();
+    if tools.is_empty() {
+        println!("No tools available");
+        return;
+    }
+
+    let width = tools.iter()
+        .filter_map(|t| t["name"].as_str())
+        .map(|n| n.chars().count())
+        .max()
+        .unwrap_or(0);
+    for tool in tools {
+        let name = tool["name"].as_str().unwrap_or("?");
+        let description = tool["description"].as_str()
+            .and_then(|d| d.lines().map(str::trim).find(|l| !l.is_empty()))
+            .unwrap_or("");
+        let description = if description.chars().count() > MAX_DESCRIPTION {
+            let cut: String = description.chars().take(MAX_DESCRIPTION - 3).collect();
+            format!("{}...", cut.trim_end())
+        } else {
+            description.to_string()
+        };
+        let padded = format!("{:width$}", name, width = width);
+        println!("  {}  {}", paint(&padded, COLOR_KEY, color), description);
+    }
+}
+
+/// Prints a tool's contract: description, then the labeled `inputSchema` and
+/// `outputSchema` (the shape of `structuredContent`), if declared
+fn print_tool_description(tool: &Value, color: bool) -> Result<()> {
+    println!("{}", paint(tool["name"].as_str().unwrap_or("?"), COLOR_BOLD, color));
+    if let Some(description) = tool["description"].as_str() {
+        for line in description.lines() {
+            println!("  {}", line);
+        }
+    }
+    for (label, key) in [("Input schema", "inputSchema"), ("Output schema", "outputSchema")] {
+        println!();
+        match tool.get(key) {
+            Some(schema) => println!("{}:\n{}", paint(label, COLOR_KEY, color), render_json(schema, color)?),
+            None => println!("{}: none declared", paint(label, COLOR_KEY, color)),
+        }
+    }
+    Ok(())
+}
+
+/// Prints a shell result, piped through `jq <filter>` when requested
+fn print_shell_result(result: &Value, jq_filter: Option<&str>, color: bool) -> Result<()> {
+    let Some(filter) = jq_filter else {
+        println!("{}", render_json(result, color)?);
+        return Ok(());
+    };
+
+    let mut cmd = Command::new("jq");
+    if color {
+        cmd.arg("-C");
+    }
+    let mut child = cmd
+        .arg(filter)
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("Failed to run jq (is it installed?)")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(serde_json::to_string(result)?.as_bytes())?;
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(anyhow!("jq exited with {}", status));
+    }
+    Ok(())
 }
 
 // ============================================================================
-// Unix Socket Communication
+// TUI Browser
 // ============================================================================
 
-fn run_daemon(server_name: &str, profile: &ServerProfile, extra_args: Option<Vec<String>>) -> Result<()> {
-    // Use /tmp for socket with daemon's own PID
-    let socket_dir = PathBuf::from("/tmp/.mcp");
+/// Raw mode on the alternate screen; restored on drop, so errors and panics leave a usable terminal
+struct TuiTerminal;
 
-    // Ensure socket directory exists with secure permissions
-    if !socket_dir.exists() {
-        let old_umask = umask(Mode::from_bits_truncate(0o077));
-        fs::create_dir_all(&socket_dir)
-            .context("Failed to create socket directory")?;
-        umask(old_umask);
+impl TuiTerminal {
+    fn enter() -> Result<Self> {
+        terminal::enable_raw_mode().context("Failed to switch the terminal to raw mode")?;
+        let guard = TuiTerminal;
+        execute!(std::io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(guard)
     }
+}
 
-    let socket_path = socket_dir.join(format!("{}-{}.sock", server_name, std::process::id()));
+impl Drop for TuiTerminal {
+    fn drop(&mut self) {
+        let _ = execute!(std::io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
 
-    // Clean up old socket
-    if socket_path.exists() {
-        fs::remove_file(&socket_path)?;
+/// Sends server stderr to `path` until dropped, then says where it went if anything was written
+struct StderrTeeRedirect(PathBuf);
+
+impl StderrTeeRedirect {
+    fn to(path: PathBuf) -> Result<Self> {
+        let file = fs::File::create(&path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        *STDERR_TEE_REDIRECT.lock().unwrap() = Some(file);
+        Ok(StderrTeeRedirect(path))
     }
+}
 
-    let listener = UnixListener::bind(&socket_path)
-        .context("Failed to bind Unix socket")?;
+impl Drop for StderrTeeRedirect {
+    fn drop(&mut self) {
+        *STDERR_TEE_REDIRECT.lock().unwrap() = None;
+        if fs::metadata(&self.0).is_ok_and(|m| m.len() > 0) {
+            eprintln!("Server stderr from this session: {}", self.0.display());
+        }
+    }
+}
 
-    // Restrict socket permissions to owner only (0600)
-    fs::set_permissions(&socket_path, fs::Permissions::from_mode(0o600))
-        .context("Failed to set socket permissions")?;
+/// One argument in the call form; `kind` is the schema's `type`, empty when undeclared
+struct FormField {
+    name: String,
+    kind: String,
+    required: bool,
+    value: String,
+}
 
-    eprintln!("Daemon listening on {:?}", socket_path);
+/// The call form for a tool. Without declared properties it holds a single raw JSON field.
+struct CallForm {
+    tool: String,
+    fields: Vec<FormField>,
+    focus: usize,
+    raw: bool,
+}
 
-    // Start MCP server instance
-    let mut mcp = McpClient::start(profile, extra_args, server_name)?;
+impl CallForm {
+    fn new(tool: &Value) -> Self {
+        let schema = &tool["inputSchema"];
+        let required: Vec<&str> = schema["required"].as_array()
+            .map(|names| names.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        let fields: Vec<FormField> = schema["properties"].as_object()
+            .map(|properties| properties.iter().map(|(name, property)| FormField {
+                name: name.clone(),
+                kind: property["type"].as_str().unwrap_or_default().to_string(),
+                required: required.contains(&name.as_str()),
+                value: String::new(),
+            }).collect())
+            .unwrap_or_default();
+        let raw = fields.is_empty();
+        let fields = if raw {
+            vec![FormField { name: "arguments".to_string(), kind: "object".to_string(), required: false, value: String::new() }]
+        } else {
+            fields
+        };
+        CallForm { tool: tool["name"].as_str().unwrap_or_default().to_string(), fields, focus: 0, raw }
+    }
 
-    // Handle connections
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                if let Err(e) = handle_client(&mut mcp, stream) {
-                    eprintln!("Client error: {}", e);
+    /// Builds the call arguments: string fields are taken verbatim, others parsed as JSON
+    /// (falling back to a string), and empty fields are left out
+    fn arguments(&self) -> Result<Value> {
+        if self.raw {
+            let text = self.fields[0].value.trim();
+            return serde_json::from_str(if text.is_empty() { "{}" } else { text })
+                .context("Invalid JSON arguments");
+        }
+        let mut args = serde_json::Map::new();
+        for field in &self.fields {
+            if field.value.is_empty() {
+                if field.required {
+                    return Err(anyhow!("Missing required argument '{}'", field.name));
                 }
+                continue;
             }
-            Err(e) => {
-                eprintln!("Connection error: {}", e);
-            }
+            let value = match field.kind.as_str() {
+                "string" => Value::String(field.value.clone()),
+                _ => serde_json::from_str(&field.value).unwrap_or_else(|_| Value::String(field.value.clone())),
+            };
+            args.insert(field.name.clone(), value);
         }
+        Ok(Value::Object(args))
     }
-
-    Ok(())
 }
 
-fn handle_client(mcp: &mut McpClient, mut stream: UnixStream) -> Result<()> {
-    const MAX_REQUEST_SIZE: usize = 1024 * 1024; // 1MB limit
-
-    let mut reader = BufReader::new(stream.try_clone()?);
-    let mut line = String::with_capacity(8192);
-    reader.read_line(&mut line)?;
+/// Browser state: the selected tool, the right pane's scroll offset, and the open form or last result
+struct TuiState {
+    title: String,
+    tools: Vec<Value>,
+    selected: usize,
+    scroll: usize,
+    form: Option<CallForm>,
+    editing: bool,
+    result: Option<(String, Vec<String>)>,
+    status: String,
+}
 
-    if line.len() > MAX_REQUEST_SIZE {
-        return Err(anyhow!("Request too large: {} bytes", line.len()));
+impl TuiState {
+    /// Lines of the right pane, before wrapping
+    fn detail_lines(&self) -> Result<Vec<String>> {
+        if let Some((heading, lines)) = &self.result {
+            return Ok(std::iter::once(heading.clone()).chain(std::iter::once(String::new())).chain(lines.iter().cloned()).collect());
+        }
+        let Some(tool) = self.tools.get(self.selected) else {
+            return Ok(vec!["Server has no tools".to_string()]);
+        };
+        if let (true, Some(form)) = (self.editing, &self.form) {
+            let mut lines = vec![format!("Arguments for {}", form.tool), String::new()];
+            for (i, field) in form.fields.iter().enumerate() {
+                let marker = if i == form.focus { ">" } else { " " };
+                let required = if field.required { "*" } else { "" };
+                let kind = if field.kind.is_empty() { String::new() } else { format!(" ({})", field.kind) };
+                let cursor = if i == form.focus { "_" } else { "" };
+                lines.push(format!("{} {}{}{}: {}{}", marker, field.name, required, kind, field.value, cursor));
+            }
+            lines.push(String::new());
+            lines.push(if form.raw {
+                "No properties declared; enter the arguments as one JSON object (empty for {})."
+            } else {
+                "Strings are taken as typed; other values are JSON. Empty fields are omitted."
+            }.to_string());
+            return Ok(lines);
+        }
+        let mut lines = vec![tool["name"].as_str().unwrap_or("?").to_string(), String::new()];
+        if let Some(description) = tool["description"].as_str() {
+            lines.extend(description.lines().map(str::to_string));
+            lines.push(String::new());
+        }
+        lines.push("Input schema:".to_string());
+        match tool.get("inputSchema") {
+            Some(schema) => lines.extend(serde_json::to_string_pretty(schema)?.lines().map(str::to_string)),
+            None => lines.push("none declared".to_string()),
+        }
+        Ok(lines)
     }
 
-    let request: Value = serde_json::from_str(line.trim())
-        .context("Invalid JSON-RPC request")?;
-
-    let method = request["method"].as_str()
-        .ok_or_else(|| anyhow!("Missing method"))?;
-
-    let response = match method {
-        "tools/call" => {
-            let params = &request["params"];
-            let tool_name = params["name"].as_str()
-                .ok_or_else(|| anyhow!("Missing tool name"))?;
-            let args = params["arguments"].clone();
-
-            match mcp.call_tool(tool_name, args) {
-                Ok(result) => json!({
-                    "jsonrpc": "2.0",
-                    "id": request["id"],
-                    "result": result
-                }),
-                Err(e) => json!({
-                    "jsonrpc": "2.0",
-                    "id": request["id"],
-                    "error": {"message": e.to_string()}
-                }),
-            }
+    fn draw(&self, out: &mut impl Write) -> Result<()> {
+        let (cols, rows) = terminal::size()?;
+        let (cols, rows) = (cols as usize, rows as usize);
+        if cols < 20 || rows < 4 {
+            queue!(out, terminal::Clear(terminal::ClearType::All), cursor::MoveTo(0, 0), style::Print("Terminal too small"))?;
+            return Ok(out.flush()?);
         }
-        "tools/list" => {
-            match mcp.list_tools() {
-                Ok(result) => json!({
-                    "jsonrpc": "2.0",
-                    "id": request["id"],
-                    "result": result
-                }),
-                Err(e) => json!({
-                    "jsonrpc": "2.0",
-                    "id": request["id"],
-                    "error": {"message": e.to_string()}
-                }),
+        let body_rows = rows - 2;
+        let longest = self.tools.iter().map(|t| t["name"].as_str().unwrap_or("?").chars().count()).max().unwrap_or(0);
+        let left = (longest + 2).clamp(10, cols / 3);
+        let right = cols - left - 1;
+
+        let first = self.selected.saturating_sub(body_rows - 1);
+        let detail: Vec<String> = self.detail_lines()?.iter().flat_map(|line| wrap_chars(line, right)).collect();
+
+        queue!(out, terminal::Clear(terminal::ClearType::All), cursor::MoveTo(0, 0))?;
+        queue!(out, style::SetAttribute(style::Attribute::Reverse), style::Print(fit_width(&self.title, cols)), style::SetAttribute(style::Attribute::Reset))?;
+        for row in 0..body_rows {
+            queue!(out, cursor::MoveTo(0, (row + 1) as u16))?;
+            let index = first + row;
+            match self.tools.get(index) {
+                Some(tool) => {
+                    let name = fit_width(&format!(" {}", tool["name"].as_str().unwrap_or("?")), left);
+                    if index == self.selected {
+                        queue!(out, style::SetAttribute(style::Attribute::Reverse), style::Print(name), style::SetAttribute(style::Attribute::Reset))?;
+                    } else {
+                        queue!(out, style::Print(name))?;
+                    }
+                }
+                None => queue!(out, style::Print(" ".repeat(left)))?,
             }
+            let line = detail.get(self.scroll + row).map(String::as_str).unwrap_or("");
+            queue!(out, style::Print("│"), style::Print(fit_width(line, right)))?;
         }
-        _ => json!({
-            "jsonrpc": "2.0",
-            "id": request["id"],
-            "error": {"message": format!("Unknown method: {}", method)}
-        }),
-    };
+        let help = if self.editing {
+            "Tab/↓ next  ↑ prev  Enter run  Esc cancel"
+        } else if self.result.is_some() {
+            "↑/↓ select  Enter call again  PgUp/PgDn scroll  Esc back  q quit"
+        } else {
+            "↑/↓ select  Enter call  PgUp/PgDn scroll  q quit"
+        };
+        let status = if self.status.is_empty() { help.to_string() } else { format!("{}  |  {}", self.status, help) };
+        queue!(out, cursor::MoveTo(0, (rows - 1) as u16), style::Print(fit_width(&status, cols)))?;
+        Ok(out.flush()?)
+    }
 
-    let response_str = serde_json::to_string(&response)?;
-    writeln!(stream, "{}", response_str)?;
+    fn select(&mut self, index: usize) {
+        if index != self.selected {
+            self.selected = index;
+            self.form = None;
+            self.result = None;
+        }
+        self.scroll = 0;
+    }
 
-    Ok(())
+    /// Runs the form's call and shows its result (or error) in the right pane
+    fn run_form(&mut self, source: &mut ToolSource, out: &mut impl Write) -> Result<()> {
+        let Some(form) = &self.form else { return Ok(()) };
+        let args = match form.arguments() {
+            Ok(args) => args,
+            Err(e) => {
+                self.status = e.to_string();
+                return Ok(());
+            }
+        };
+        let tool = form.tool.clone();
+        self.status = format!("Calling {}...", tool);
+        self.draw(out)?;
+
+        let started = Instant::now();
+        let outcome = source.call_tool(&tool, args);
+        let elapsed = started.elapsed();
+        self.result = Some(match outcome {
+            Ok(result) => (
+                format!("Result of {} ({} ms)", tool, elapsed.as_millis()),
+                serde_json::to_string_pretty(&result)?.lines().map(str::to_string).collect(),
+            ),
+            Err(e) => (
+                format!("Error from {} ({} ms)", tool, elapsed.as_millis()),
+                format!("{:#}", e).lines().map(str::to_string).collect(),
+            ),
+        });
+        self.editing = false;
+        self.scroll = 0;
+        self.status.clear();
+        Ok(())
+    }
 }
 
-fn connect_to_daemon(server_name: &str) -> Result<UnixStream> {
-    let daemon_mgr = DaemonManager::new(server_name);
-    let socket_path = daemon_mgr.get_socket_path()
-        .context("Failed to get socket path (daemon not started?)")?;
-
-    let stream = UnixStream::connect(&socket_path)
-        .context("Failed to connect to daemon (is it running?)")?;
-
-    // Set timeouts
-    stream.set_read_timeout(Some(Duration::from_secs(30)))
-        .context("Failed to set read timeout")?;
-    stream.set_write_timeout(Some(Duration::from_secs(30)))
-        .context("Failed to set write timeout")?;
-
-    Ok(stream)
+/// Pads or cuts `text` to exactly `width` characters
+fn fit_width(text: &str, width: usize) -> String {
+    let text = text.replace('\t', "    ");
+    let mut fitted: String = text.chars().take(width).collect();
+    let len = fitted.chars().count();
+    fitted.extend(std::iter::repeat_n(' ', width - len));
+    fitted
 }
 
-fn send_daemon_request(mut stream: UnixStream, request: Value) -> Result<Value> {
-    let request_str = serde_json::to_string(&request)?;
-    writeln!(stream, "{}", request_str)?;
+/// Splits `text` into chunks of at most `width` characters
+fn wrap_chars(text: &str, width: usize) -> Vec<String> {
+    let chars: Vec<char> = text.replace('\t', "    ").chars().collect();
+    if chars.is_empty() || width == 0 {
+        return vec![String::new()];
+    }
+    chars.chunks(width).map(|chunk| chunk.iter().collect()).collect()
+}
 
-    let mut reader = BufReader::new(stream);
-    let mut line = String::new();
-    reader.read_line(&mut line)?;
+/// Runs the interactive tool browser until the user quits
+fn run_tui(server_name: &str, mut source: ToolSource, tools: Value) -> Result<()> {
+    let via = match source {
+        ToolSource::Daemon(_) => "daemon",
+        ToolSource::Direct(..) => "direct",
+    };
+    let tools = tools["tools"].as_array().cloned().unwrap_or_default();
+    let mut state = TuiState {
+        title: format!(" mcp-valve: {} ({}, {} tools)", server_name, via, tools.len()),
+        tools,
+        selected: 0,
+        scroll: 0,
+        form: None,
+        editing: false,
+        result: None,
+        status: String::new(),
+    };
 
-    let response: Value = serde_json::from_str(line.trim())
-        .context("Invalid JSON-RPC response")?;
+    // A server started here would write its stderr over the screen; declared before
+    // the terminal guard so the note about it prints after the screen is restored
+    let _stderr = match source {
+        ToolSource::Direct(..) => Some(StderrTeeRedirect::to(server_profile_dir(server_name).join("tui.log"))?),
+        ToolSource::Daemon(_) => None,
+    };
+    let _terminal = TuiTerminal::enter()?;
+    let mut out = std::io::stdout();
+    loop {
+        state.draw(&mut out)?;
+        let Event::Key(KeyEvent { code, modifiers, kind, .. }) = event::read()? else {
+            continue;
+        };
+        if kind == KeyEventKind::Release {
+            continue;
+        }
+        let ctrl_c = modifiers.contains(KeyModifiers::CONTROL) && code == KeyCode::Char('c');
+
+        if state.editing {
+            let Some(form) = state.form.as_mut() else { continue };
+            state.status.clear();
+            match code {
+                _ if ctrl_c => state.editing = false,
+                KeyCode::Esc => state.editing = false,
+                KeyCode::Enter => state.run_form(&mut source, &mut out)?,
+                KeyCode::Tab | KeyCode::Down => form.focus = (form.focus + 1) % form.fields.len(),
+                KeyCode::BackTab | KeyCode::Up => form.focus = (form.focus + form.fields.len() - 1) % form.fields.len(),
+                KeyCode::Backspace => {
+                    form.fields[form.focus].value.pop();
+                }
+                KeyCode::Char(c) if !modifiers.contains(KeyModifiers::CONTROL) => form.fields[form.focus].value.push(c),
+                _ => {}
+            }
+            continue;
+        }
 
-    if let Some(error) = response.get("error") {
-        return Err(anyhow!("Daemon error: {}", error));
+        let last = state.tools.len().saturating_sub(1);
+        match code {
+            _ if ctrl_c => break,
+            KeyCode::Char('q') => break,
+            KeyCode::Esc if state.result.is_some() => {
+                state.result = None;
+                state.scroll = 0;
+            }
+            KeyCode::Esc => break,
+            KeyCode::Up | KeyCode::Char('k') => state.select(state.selected.saturating_sub(1)),
+            KeyCode::Down | KeyCode::Char('j') => state.select((state.selected + 1).min(last)),
+            KeyCode::Home => state.select(0),
+            KeyCode::End => state.select(last),
+            KeyCode::PageUp => state.scroll = state.scroll.saturating_sub(10),
+            KeyCode::PageDown => state.scroll += 10,
+            KeyCode::Enter => {
+                if let Some(tool) = state.tools.get(state.selected) {
+                    if state.form.is_none() {
+                        state.form = Some(CallForm::new(tool));
+                    }
+                    state.result = None;
+                    state.scroll = 0;
+                    state.editing = true;
+                }
+            }
+            _ => {}
+        }
     }
-
-    Ok(response["result"].clone())
+    Ok(())
 }
 
-fn call_via_daemon(server_name: &str, tool: &str, args: Value) -> Result<Value> {
-    let stream = connect_to_daemon(server_name)?;
-
-    let request = json!({
-        "jsonrpc": "2.0",
-        "id": 1,
-        "method": "tools/call",
-        "params": {
-            "name": tool,
-            "arguments": args
-        }
-    });
+// ============================================================================
+// Main
+// ============================================================================
 
-    send_daemon_request(stream, request)
+/// Set from `--error-format json` once the CLI is parsed
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+
+/// Exit code for a JSON-RPC error code under `--error-format json`
+fn rpc_exit_code(code: i64) -> i32 {
+    match code {
+        -32700 => 65, // Parse error (EX_DATAERR)
+        -32600 => 64, // Invalid request (EX_USAGE)
+        -32601 => 38, // Method not found (ENOSYS)
+        -32602 => 22, // Invalid params (EINVAL)
+        -32603 => 70, // Internal error (EX_SOFTWARE)
+        _ => 1,
+    }
 }
 
-fn list_tools_via_daemon(server_name: &str) -> Result<Value> {
-    let stream = connect_to_daemon(server_name)?;
+fn main() {
+    let Err(e) = run() else {
+        return;
+    };
 
-    let request = json!({
-        "jsonrpc": "2.0",
-        "id": 1,
-        "method": "tools/list",
-        "params": {}
-    });
+    let failure = Failure::of(&e);
+    let exit_code = failure.map_or(1, Failure::exit_code);
 
-    send_daemon_request(stream, request)
-}
+    if !JSON_ERRORS.load(Ordering::SeqCst) {
+        eprintln!("{} {:?}", stderr_label("Error:", COLOR_ERROR), e);
+        std::process::exit(exit_code);
+    }
 
-// ============================================================================
-// Main
-// ============================================================================
+    let rpc = rpc_error(&e);
+    let mut error = match rpc {
+        Some(rpc) => rpc.error.clone(),
+        None => json!({"message": format!("{:#}", e)}),
+    };
+    if let Some(failure) = failure {
+        error["kind"] = json!(failure.name());
+    }
+    eprintln!("{}", json!({"error": error}));
+    let rpc_exit = rpc.and_then(RpcError::code).map(rpc_exit_code).filter(|&code| code != 1);
+    std::process::exit(rpc_exit.unwrap_or(exit_code));
+}
 
-fn main() -> Result<()> {
+fn run() -> Result<()> {
     // Handle internal daemon command BEFORE clap parsing
     let args: Vec<String> = std::env::args().collect();
     if args.len() > 1 && args[1] == "__internal_daemon" {
-        // Find --server, --server-args, and --config by manual parsing
+        // Find --server by manual parsing; the launch parses the rest
         let server_name = args.iter()
             .position(|a| a == "--server")
             .and_then(|i| args.get(i + 1))
             .ok_or_else(|| anyhow!("__internal_daemon requires --server"))?
             .clone();
 
-        let extra_args = args.iter()
-            .position(|a| a == "--server-args")
-            .and_then(|i| args.get(i + 1))
-            .and_then(|s| serde_json::from_str::<Vec<String>>(s).ok());
-
-        let cli_config = args.iter()
-            .position(|a| a == "--config" || a == "-c")
-            .and_then(|i| args.get(i + 1))
-            .map(PathBuf::from);
+        let launch = DaemonLaunch::from_daemon_args(&args[2..])?;
+        adopt_daemon_scope_env();
+
+        // Also used to re-read the profile on SIGHUP
+        let load_profile = || -> Result<ServerProfile> {
+            let config = load_selected_config(&launch.config)?;
+            let mut profile = config.servers.get(&server_name)
+                .ok_or_else(|| anyhow!("Server '{}' not found", server_name))?
+                .clone();
+            profile.env.extend(launch.env.clone());
+            apply_policy_overrides(&mut profile, &launch.options.only_tools, launch.options.read_only);
+            if launch.options.keep_alive.is_some() {
+                profile.idle_timeout = launch.options.keep_alive;
+            }
+            Ok(profile)
+        };
+        let profile = load_profile()?;
 
-        let config = load_server_config(cli_config)?;
-        let profile = config.servers.get(&server_name)
-            .ok_or_else(|| anyhow!("Server '{}' not found", server_name))?;
+        if let Some(path) = &launch.options.debug_transcript {
+            open_transcript(path)?;
+        }
 
-        return run_daemon(&server_name, profile, extra_args);
+        return run_daemon(&server_name, &profile, &launch, &load_profile);
     }
 
     // Filter out empty arguments
@@ -971,40 +7038,274 @@ fn main() -> Result<()> {
         .collect();
 
     let cli = Cli::parse_from(filtered_args);
+    JSON_ERRORS.store(cli.error_format == ErrorFormat::Json, Ordering::SeqCst);
+    if let Some(start) = cli.json_rpc_id_start {
+        NEXT_DAEMON_ID.store(start, Ordering::SeqCst);
+    }
+    ACCEPT_GZIP.store(cli.compress, Ordering::SeqCst);
+    CLIENT_READ_ONLY.store(cli.read_only, Ordering::SeqCst);
+    let _ = CLIENT_ONLY_TOOLS.set(cli.only_tool.clone());
+    SHOW_DAEMON_LOGS.store(cli.show_daemon_logs, Ordering::SeqCst);
+    STRICT_CONFIG.store(cli.strict_config, Ordering::SeqCst);
+    COMPACT_JSON.store(cli.compact, Ordering::SeqCst);
+    let _ = DAEMON_TIMEOUTS.set(DaemonTimeouts {
+        connect: cli.timeout_connect,
+        read: cli.timeout_read,
+        write: cli.timeout_write,
+    });
+
+    // The daemon gets these three from `start` (see `daemon_scope_env`)
+    if let Some(dir) = &cli.profile_dir {
+        let dir = std::path::absolute(dir).context("Invalid --profile-dir")?;
+        let _ = PROFILE_ROOT.set(dir);
+    }
+    if let Some(dir) = &cli.working_dir {
+        let dir = std::path::absolute(dir).context("Invalid --working-dir")?;
+        if !dir.is_dir() {
+            return Err(Failure::Config.wrap(anyhow!("--working-dir {} is not a directory", dir.display())));
+        }
+        let _ = WORKING_DIR.set(dir);
+    }
+    if let Some(instance) = &cli.instance {
+        if instance.is_empty() || sanitize_server_name(instance) != *instance {
+            return Err(anyhow!(
+                "Invalid --instance '{}': use letters, digits, '-' or '_'",
+                instance
+            ));
+        }
+        let _ = INSTANCE.set(instance.clone());
+    }
+
+    // Absolute so the daemon records into the same file as this process
+    let debug_transcript = match &cli.debug_transcript {
+        Some(path) => Some(std::path::absolute(path).context("Invalid --debug-transcript")?),
+        None => None,
+    };
+    if let Some(path) = &debug_transcript {
+        open_transcript(path)?;
+    }
+
+    let client_options = ClientOptions {
+        log_file: cli.log_file.clone(),
+        strict_protocol: cli.strict_protocol,
+        debug_transcript,
+        only_tools: cli.only_tool.clone(),
+        read_only: cli.read_only,
+        response_timeout: cli.request_timeout,
+        max_response_bytes: cli.max_response_bytes,
+        wait_ready: cli.wait_ready,
+        start_timeout: cli.start_timeout,
+        append_log: cli.append_log,
+        keep_alive: None,
+        id_start: cli.json_rpc_id_start,
+    };
+    let color_choice = if cli.no_color { ColorChoice::Never } else { cli.color };
+    let color = use_color(color_choice, std::io::stdout().is_terminal());
+    STDERR_COLOR.store(use_color(color_choice, std::io::stderr().is_terminal()), Ordering::SeqCst);
+    let output = OutputOptions {
+        color,
+        out: cli.out.clone(),
+        pager: match (cli.pager, cli.no_pager) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        },
+    };
+
+    match cli.command {
+        Commands::ListServers => {
+            let config = load_server_config(cli.config.clone())?;
+            println!("Configured MCP servers:\n");
+            for (name, profile) in &config.servers {
+                let desc = if profile.description.is_empty() {
+                    "No description"
+                } else {
+                    &profile.description
+                };
+                println!("  {}: {}", paint(name, COLOR_BOLD, color), desc);
+                match &profile.container {
+                    Some(container) => println!("    Container: {} ({})", container.image, container.runtime),
+                    None => println!("    Command: {:?}", profile.command),
+                }
+                if !profile.default_args.is_empty() {
+                    println!("    Default args: {:?}", profile.default_args);
+                }
+                if profile.supports_daemon {
+                    println!("    Daemon support: yes");
+                }
+                if cli.verbose {
+                    println!("    Source: {}", config.source(name).display());
+                }
+                println!();
+            }
+
+            if !config.aliases.is_empty() {
+                println!("Aliases:\n");
+                let mut aliases: Vec<_> = config.aliases.iter().collect();
+                aliases.sort();
+                for (alias, target) in aliases {
+                    let missing = if config.servers.contains_key(target) { "" } else { " (missing server)" };
+                    println!("  {} (alias) -> {}{}", paint(alias, COLOR_BOLD, color), target, missing);
+                }
+                println!();
+            }
+            Ok(())
+        }
+
+        Commands::Config { action: ConfigAction::Export { ndjson, format } } => {
+            let config = load_server_config(cli.config.clone())?;
+            match format {
+                ConfigFormat::Valve => export_config(&config, ndjson),
+                ConfigFormat::McpServers => print_result(&to_mcp_servers(&config), &output),
+            }
+        }
+
+        Commands::Config { action: ConfigAction::Schema } => print_result(&config_schema(), &output),
+
+        Commands::Config { action: ConfigAction::Edit } => {
+            let path = get_config_path(cli.config.clone())?;
+            edit_config(&path)
+        }
+
+        Commands::Config { action: ConfigAction::Import { file, ndjson, format, on_conflict } } => {
+            let content = if file == "-" {
+                let mut buffer = String::new();
+                std::io::stdin()
+                    .read_to_string(&mut buffer)
+                    .context("Failed to read profiles from stdin")?;
+                buffer
+            } else {
+                fs::read_to_string(&file)
+                    .with_context(|| format!("Failed to read import file: {}", file))?
+            };
+
+            let profiles = match format {
+                ConfigFormat::Valve => parse_import(&content, ndjson)?,
+                ConfigFormat::McpServers => from_mcp_servers(&content)?,
+            };
+            let path = get_config_path(cli.config.clone())?;
+            import_config(&path, profiles, on_conflict)
+        }
+
+        Commands::Call { tool, args, jsonl, repeat, concurrency, quiet, watch, stream, args_stdin_jsonl, fail_fast, select, output_template, strict, positional, arg_file, arg_file_contents, retry_on_tool_error, idempotent_tool, keep_alive, auto_daemon } => {
+            let config = load_server_config(cli.config.clone())?;
+            let server_name = match cli.server {
+                Some(name) => name,
+                None => pick_server(&config)?,
+            };
+            let (server_name, profile) = config.resolve(&server_name)?;
+
+            if cli.dump_env {
+                let mut profile = profile.clone();
+                profile.env.extend(parse_env_overrides(&cli.env)?);
+                print_env_dump(&profile, cli.show_secrets);
+                return Ok(());
+            }
+
+            let daemon_mgr = DaemonManager::new(&server_name);
+            let auto_start = keep_alive.is_some() || auto_daemon || profile.auto_daemon;
+            if auto_start && !daemon_mgr.is_running().unwrap_or(false) {
+                if let Some(idle) = keep_alive {
+                    eprintln!("Starting ephemeral daemon for '{}' (stops after {}s idle)", server_name, idle);
+                }
+                let launch = DaemonLaunch {
+                    config: config.selection.clone(),
+                    server_args: parse_server_args(cli.server_args.as_deref(), cli.server_args_append.as_deref(), profile)?,
+                    env: parse_env_overrides(&cli.env)?,
+                    options: ClientOptions { keep_alive, ..client_options.clone() },
+                };
+                daemon_mgr.start(profile, &launch)?;
+            }
+
+            if args_stdin_jsonl {
+                let tool = tool.context("Tool name required")?;
+                let mut profile = profile.clone();
+                profile.env.extend(parse_env_overrides(&cli.env)?);
+                let extra_args = parse_server_args(cli.server_args.as_deref(), cli.server_args_append.as_deref(), &profile)?;
+                let mut source = ToolSource::open(&server_name, &profile, extra_args, &client_options, cli.no_fallback, cli.verbose)?;
+                return call_each_stdin_line(&mut source, &tool, fail_fast);
+            }
+
+            // Require daemon to be running
+            if !daemon_mgr.is_running().unwrap_or(false) {
+                return Err(daemon_not_running_error(&server_name));
+            }
+            if cli.verbose {
+                if let Ok(socket) = daemon_mgr.get_socket_path() {
+                    eprintln!("Using daemon: {}", socket.display());
+                }
+            }
+
+            if stream {
+                return stream_calls(&server_name, fail_fast);
+            }
+            let tool = tool.context("Tool name required")?;
+
+            // Parse tool arguments
+            let json_str = if args == "-" {
+                let mut buffer = String::new();
+                std::io::stdin()
+                    .read_to_string(&mut buffer)
+                    .context("Failed to read JSON from stdin")?;
+                buffer
+            } else {
+                args
+            };
+
+            let mut args_json: Value = match positional {
+                Some(values) => positional_arguments(&server_name, &tool, &values)?,
+                None => serde_json::from_str(&expand_args_env(&json_str)?).context("Invalid JSON arguments")?,
+            };
+            add_file_arguments(&mut args_json, &arg_file, &arg_file_contents, &server_name, profile)?;
+
+            if let Some(path) = watch {
+                return watch_call(&server_name, &tool, &args_json, &path, color);
+            }
+
+            if repeat > 1 {
+                let options = RepeatOptions { repeat, concurrency, quiet };
+                return run_repeated_call(&server_name, &tool, &args_json, &options, jsonl, color);
+            }
 
-    match cli.command {
-        Commands::ListServers => {
-            let config = load_server_config(cli.config.clone())?;
-            println!("Configured MCP servers:\n");
-            for (name, profile) in config.servers {
-                let desc = if profile.description.is_empty() {
-                    "No description"
-                } else {
-                    &profile.description
-                };
-                println!("  {}: {}", name, desc);
-                println!("    Command: {:?}", profile.command);
-                if !profile.default_args.is_empty() {
-                    println!("    Default args: {:?}", profile.default_args);
+            let result = if retry_on_tool_error > 0 {
+                if !idempotent_tool.contains(&tool) && !profile.idempotent_tools.contains(&tool) {
+                    return Err(Failure::Config.wrap(anyhow!(
+                        "Refusing to retry '{}': it is not marked idempotent (add --idempotent-tool {} or list it in idempotent_tools)",
+                        tool,
+                        tool
+                    )));
                 }
-                if profile.supports_daemon {
-                    println!("    Daemon support: yes");
+                call_with_retries(&server_name, &tool, &args_json, retry_on_tool_error, cli.verbose)
+            } else {
+                call_via_daemon(&server_name, &tool, args_json)
+            };
+
+            if jsonl {
+                match &output.out {
+                    Some(path) => write_output_file(path, &jsonl_record(&tool, &result).to_string())?,
+                    None => print_jsonl_record(&tool, &result)?,
                 }
-                println!();
+                return result.map(|_| ());
+            }
+
+            let result = result?;
+            if let Some(template) = output_template {
+                let text = render_output_template(&template, &result, strict)?;
+                return print_selected(&Value::String(text), &output);
+            }
+            match select {
+                Some(path) => print_selected(select_path(&result, &path)?, &output),
+                None => print_result(&result, &output),
             }
-            Ok(())
         }
 
-        Commands::Call { tool, args } => {
+        Commands::Batch { file, jsonl } => {
             let server_name = cli.server.ok_or_else(|| {
                 anyhow!("--server required. Use 'list-servers' to see available servers.")
             })?;
 
             let config = load_server_config(cli.config.clone())?;
-            let _profile = config
-                .servers
-                .get(&server_name)
-                .ok_or_else(|| anyhow!("Server '{}' not found in config", server_name))?;
+            let (server_name, _profile) = config.resolve(&server_name)?;
 
             // Require daemon to be running
             let daemon_mgr = DaemonManager::new(&server_name);
@@ -1012,36 +7313,209 @@ fn main() -> Result<()> {
                 return Err(daemon_not_running_error(&server_name));
             }
 
-            // Parse tool arguments
-            let json_str = if args == "-" {
+            let content = if file == "-" {
                 let mut buffer = String::new();
                 std::io::stdin()
                     .read_to_string(&mut buffer)
-                    .context("Failed to read JSON from stdin")?;
+                    .context("Failed to read batch from stdin")?;
                 buffer
             } else {
-                args
+                fs::read_to_string(&file)
+                    .with_context(|| format!("Failed to read batch file: {}", file))?
+            };
+
+            let entries: Vec<Value> =
+                serde_json::from_str(&content).context("Batch must be a JSON array")?;
+            let calls = entries
+                .iter()
+                .map(|entry| {
+                    let tool = entry["tool"].as_str()
+                        .ok_or_else(|| anyhow!("Batch entry missing \"tool\": {}", entry))?;
+                    let args = entry.get("args").cloned().unwrap_or_else(|| json!({}));
+                    Ok((tool.to_string(), args))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            let results = batch_via_daemon(&server_name, &calls)?;
+            let failed = results.iter().filter(|r| r.is_err()).count();
+
+            if jsonl {
+                for ((tool, _), result) in calls.iter().zip(&results) {
+                    print_jsonl_record(tool, result)?;
+                }
+            } else {
+                let records: Vec<Value> = calls
+                    .iter()
+                    .zip(&results)
+                    .map(|((tool, _), result)| jsonl_record(tool, result))
+                    .collect();
+                print_result(&Value::Array(records), &output)?;
+            }
+
+            if failed > 0 {
+                return Err(anyhow!("{} of {} batched calls failed", failed, calls.len()));
+            }
+            Ok(())
+        }
+
+        Commands::Index { refresh, timeout } => {
+            let config = load_server_config(cli.config.clone())?;
+            let path = tools_index_path(&config);
+
+            if refresh || !path.exists() {
+                let options = ClientOptions {
+                    response_timeout: Some(timeout),
+                    ..client_options.clone()
+                };
+                let index = build_tools_index(&config, &options, job_count(cli.jobs));
+                fs::write(&path, serde_json::to_string_pretty(&index)?)
+                    .with_context(|| format!("Failed to write {}", path.display()))?;
+                eprintln!("Index written to {}", path.display());
+            }
+
+            let index: serde_json::Map<String, Value> = serde_json::from_str(&fs::read_to_string(&path)?)
+                .with_context(|| format!("Invalid tools index {}", path.display()))?;
+            print_index_summary(&index, color);
+            Ok(())
+        }
+
+        Commands::SearchTools { query } => {
+            let config = load_server_config(cli.config.clone())?;
+            let path = tools_index_path(&config);
+
+            let catalog: serde_json::Map<String, Value> = if path.exists() {
+                serde_json::from_str(&fs::read_to_string(&path)?)
+                    .with_context(|| format!("Invalid tools index {}", path.display()))?
+            } else {
+                eprintln!("No tools index (run 'index'); searching running daemons");
+                let mut names: Vec<&String> = config.servers.keys()
+                    .filter(|name| DaemonManager::new(name).is_running().unwrap_or(false))
+                    .collect();
+                names.sort();
+                let results = run_parallel(&names, job_count(cli.jobs), |name| list_tools_via_daemon(name));
+                names
+                    .into_iter()
+                    .zip(results)
+                    .filter_map(|(name, result)| match result {
+                        Ok(result) => Some((name.clone(), result["tools"].clone())),
+                        Err(e) => {
+                            eprintln!("Skipping '{}': {}", name, e);
+                            None
+                        }
+                    })
+                    .collect()
             };
 
-            let args_json: Value =
-                serde_json::from_str(&json_str).context("Invalid JSON arguments")?;
+            let mut found = 0;
+            for (server, tools) in &catalog {
+                let matches: Vec<&Value> = tools.as_array()
+                    .map(|tools| tools.iter().filter(|t| tool_matches(t, &query)).collect())
+                    .unwrap_or_default();
+                if matches.is_empty() {
+                    continue;
+                }
+                found += matches.len();
+                println!("{}:", paint(server, COLOR_BOLD, color));
+                print_tool_summary(&json!({"tools": matches}), color);
+            }
+            if found == 0 {
+                return Err(anyhow!("No tools matching '{}'", query));
+            }
+            Ok(())
+        }
 
-            let result = call_via_daemon(&server_name, &tool, args_json)?;
+        Commands::ListTools { count: true, all: true } => {
+            let config = load_server_config(cli.config.clone())?;
+            let mut names: Vec<&String> = config.servers.keys()
+                .filter(|name| DaemonManager::new(name).is_running().unwrap_or(false))
+                .collect();
+            names.sort();
+            if names.is_empty() {
+                return Err(Failure::Unavailable.wrap(anyhow!("No server has a running daemon")));
+            }
 
-            println!("{}", serde_json::to_string_pretty(&result)?);
+            let results = run_parallel(&names, job_count(cli.jobs), |name| list_tools_via_daemon(name));
+            let counts: serde_json::Map<String, Value> = names
+                .into_iter()
+                .zip(results)
+                .filter_map(|(name, result)| match result {
+                    Ok(result) => Some((name.clone(), result["tools"].clone())),
+                    Err(e) => {
+                        eprintln!("Skipping '{}': {}", name, e);
+                        None
+                    }
+                })
+                .collect();
+            print_index_summary(&counts, color);
             Ok(())
         }
 
-        Commands::ListTools => {
-            let server_name = cli.server.ok_or_else(|| {
-                anyhow!("--server required. Use 'list-servers' to see available servers.")
-            })?;
+        Commands::ListTools { count, all: _ } => {
+            let config = load_server_config(cli.config.clone())?;
+            let server_name = match cli.server {
+                Some(name) => name,
+                None => pick_server(&config)?,
+            };
+            let (server_name, _profile) = config.resolve(&server_name)?;
+
+            // Require daemon to be running
+            let daemon_mgr = DaemonManager::new(&server_name);
+            if !daemon_mgr.is_running().unwrap_or(false) {
+                return Err(daemon_not_running_error(&server_name));
+            }
+            if cli.verbose {
+                if let Ok(socket) = daemon_mgr.get_socket_path() {
+                    eprintln!("Using daemon: {}", socket.display());
+                }
+            }
+
+            let result = list_tools_via_daemon(&server_name)?;
+            if count {
+                println!("{}", result["tools"].as_array().map_or(0, Vec::len));
+                return Ok(());
+            }
+            print_result(&result, &output)
+        }
+
+        Commands::RawRequest { method, params, notification, result } => {
+            let config = load_server_config(cli.config.clone())?;
+            let server_name = match cli.server {
+                Some(name) => name,
+                None => pick_server(&config)?,
+            };
+            let (server_name, _profile) = config.resolve(&server_name)?;
+
+            // Require daemon to be running
+            let daemon_mgr = DaemonManager::new(&server_name);
+            if !daemon_mgr.is_running().unwrap_or(false) {
+                return Err(daemon_not_running_error(&server_name));
+            }
+
+            let params: Value = serde_json::from_str(&params)
+                .context("Invalid JSON params")?;
+            if result {
+                let result = request_via_daemon(&server_name, &method, params)?;
+                return print_result(&result, &output);
+            }
+            let response = request_via_daemon(
+                &server_name,
+                "valve/raw",
+                json!({"method": method, "params": params, "notification": notification}),
+            )?;
+            if notification {
+                eprintln!("Notification sent");
+                return Ok(());
+            }
+            print_result(&response, &output)
+        }
 
+        Commands::DescribeTool { name } => {
             let config = load_server_config(cli.config.clone())?;
-            let _profile = config
-                .servers
-                .get(&server_name)
-                .ok_or_else(|| anyhow!("Server '{}' not found in config", server_name))?;
+            let server_name = match cli.server {
+                Some(name) => name,
+                None => pick_server(&config)?,
+            };
+            let (server_name, _profile) = config.resolve(&server_name)?;
 
             // Require daemon to be running
             let daemon_mgr = DaemonManager::new(&server_name);
@@ -1050,20 +7524,135 @@ fn main() -> Result<()> {
             }
 
             let result = list_tools_via_daemon(&server_name)?;
-            println!("{}", serde_json::to_string_pretty(&result)?);
+            let tool = result["tools"].as_array()
+                .and_then(|tools| tools.iter().find(|t| t["name"] == name.as_str()))
+                .ok_or_else(|| anyhow!("Tool '{}' not found on server '{}'", name, server_name))?;
+            if output.out.is_some() {
+                return print_result(tool, &output);
+            }
+            print_tool_description(tool, color)
+        }
+
+        Commands::Complete { reference, argument } => {
+            let config = load_server_config(cli.config.clone())?;
+            let server_name = match cli.server {
+                Some(name) => name,
+                None => pick_server(&config)?,
+            };
+            let (server_name, _profile) = config.resolve(&server_name)?;
+
+            // Require daemon to be running
+            let daemon_mgr = DaemonManager::new(&server_name);
+            if !daemon_mgr.is_running().unwrap_or(false) {
+                return Err(daemon_not_running_error(&server_name));
+            }
+
+            for value in complete_via_daemon(&server_name, &reference, &argument)? {
+                println!("{}", value);
+            }
             Ok(())
         }
 
-        Commands::Shell => {
-            let server_name = cli.server.ok_or_else(|| {
-                anyhow!("--server required. Use 'list-servers' to see available servers.")
-            })?;
+        Commands::ToolsDiff { baseline, update } => {
+            let config = load_server_config(cli.config.clone())?;
+            let server_name = match cli.server {
+                Some(name) => name,
+                None => pick_server(&config)?,
+            };
+            let (server_name, _profile) = config.resolve(&server_name)?;
+
+            // Require daemon to be running
+            let daemon_mgr = DaemonManager::new(&server_name);
+            if !daemon_mgr.is_running().unwrap_or(false) {
+                return Err(daemon_not_running_error(&server_name));
+            }
+
+            let current = list_tools_via_daemon(&server_name)?;
+            let baseline = baseline.unwrap_or_else(|| server_profile_dir(&server_name).join("tools.json"));
+
+            let previous = match fs::read_to_string(&baseline) {
+                Ok(content) => Some(serde_json::from_str::<Value>(&content)
+                    .with_context(|| format!("Invalid JSON in {}", baseline.display()))?),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound && update => None,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Err(anyhow!(
+                        "No tools snapshot at {}\n\nCreate one with:\n  mcp-valve --server {} tools-diff --update",
+                        baseline.display(),
+                        server_name
+                    ));
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Failed to read {}", baseline.display()));
+                }
+            };
+
+            let changes = match &previous {
+                Some(previous) => print_tools_diff(previous, &current, output.color),
+                None => 0,
+            };
+
+            if update {
+                if let Some(parent) = baseline.parent().filter(|p| !p.as_os_str().is_empty()) {
+                    fs::create_dir_all(parent)
+                        .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+                }
+                fs::write(&baseline, serde_json::to_string_pretty(&current)?)
+                    .with_context(|| format!("Failed to write {}", baseline.display()))?;
+                eprintln!("Saved tools snapshot to {}", baseline.display());
+                return Ok(());
+            }
+            if changes > 0 {
+                return Err(anyhow!("{} tool(s) changed since {}", changes, baseline.display()));
+            }
+            eprintln!("No tool changes since {}", baseline.display());
+            Ok(())
+        }
+
+        Commands::Run { file } => {
+            let config = load_server_config(cli.config.clone())?;
+            let content = if file == "-" {
+                let mut buffer = String::new();
+                std::io::stdin()
+                    .read_to_string(&mut buffer)
+                    .context("Failed to read recipe from stdin")?;
+                buffer
+            } else {
+                fs::read_to_string(&file)
+                    .with_context(|| format!("Failed to read recipe: {}", file))?
+            };
+            let recipe: Recipe = serde_json::from_str(&content)
+                .context("Invalid recipe")?;
+            run_recipe(&recipe, &config, &client_options, &output)
+        }
+
+        Commands::Tui => {
+            let config = load_server_config(cli.config.clone())?;
+            let server_name = match cli.server {
+                Some(name) => name,
+                None => pick_server(&config)?,
+            };
+            let (server_name, profile) = config.resolve(&server_name)?;
+
+            let mut profile = profile.clone();
+            profile.env.extend(parse_env_overrides(&cli.env)?);
+            let extra_args = parse_server_args(cli.server_args.as_deref(), cli.server_args_append.as_deref(), &profile)?;
+            let mut source = ToolSource::open(&server_name, &profile, extra_args, &client_options, cli.no_fallback, cli.verbose)?;
 
+            let tools = source.list_tools()?;
+            if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+                eprintln!("Not a terminal; printing the tool list instead");
+                return print_result(&tools, &output);
+            }
+            run_tui(&server_name, source, tools)
+        }
+
+        Commands::Shell => {
             let config = load_server_config(cli.config.clone())?;
-            let _profile = config
-                .servers
-                .get(&server_name)
-                .ok_or_else(|| anyhow!("Server '{}' not found in config", server_name))?;
+            let server_name = match cli.server {
+                Some(name) => name,
+                None => pick_server(&config)?,
+            };
+            let (mut server_name, _profile) = config.resolve(&server_name)?;
 
             // Require daemon to be running
             let daemon_mgr = DaemonManager::new(&server_name);
@@ -1073,16 +7662,25 @@ fn main() -> Result<()> {
 
             let project = get_project_path();
             println!("MCP Shell ({}) - Project: {}", server_name, project);
-            println!("Commands: call <tool> [json], list-tools, exit");
+            println!("Commands: call <tool> [json], help, list-tools, list-resources, read <uri>,");
+            println!("          list-prompts, prompt <name> [json], complete <ref> <arg>=<partial>,");
+            println!("          use <server>, exit");
+            println!("Use $last in call args for the previous result; append | jq '<filter>' to filter output");
             println!();
 
+            let mut last: Option<Value> = None;
+            let mut tools_cache: Option<Value> = None;
+
             loop {
-                print!("mcp> ");
+                print!("mcp({})> ", server_name);
                 std::io::stdout().flush()?;
 
                 let mut input = String::new();
-                std::io::stdin().read_line(&mut input)?;
-                let input = input.trim();
+                if std::io::stdin().read_line(&mut input)? == 0 {
+                    break;
+                }
+                let (input, jq_filter) = split_jq_pipe(input.trim());
+                let jq_filter = jq_filter.as_deref();
 
                 if input.is_empty() {
                     continue;
@@ -1092,25 +7690,121 @@ fn main() -> Result<()> {
                     break;
                 }
 
+                // Switch to another server's daemon without leaving the shell
+                if let Some(name) = input.strip_prefix("use ") {
+                    match config.resolve(name.trim()) {
+                        Ok((name, _)) if DaemonManager::new(&name).is_running().unwrap_or(false) => {
+                            println!("Switched to {}", name);
+                            server_name = name;
+                            tools_cache = None;
+                        }
+                        Ok((name, _)) => eprintln!("Error: {}", daemon_not_running_error(&name)),
+                        Err(e) => eprintln!("Error: {}", e),
+                    }
+                    continue;
+                }
+
+                if input == "help" || input == "?" {
+                    if tools_cache.is_none() {
+                        match list_tools_via_daemon(&server_name) {
+                            Ok(result) => tools_cache = Some(result),
+                            Err(e) => {
+                                eprintln!("Error: {}", e);
+                                continue;
+                            }
+                        }
+                    }
+                    if let Some(tools) = &tools_cache {
+                        print_tool_summary(tools, color);
+                    }
+                    continue;
+                }
+
                 if input == "list-tools" {
                     match list_tools_via_daemon(&server_name) {
-                        Ok(result) => println!("{}", serde_json::to_string_pretty(&result)?),
+                        Ok(result) => {
+                            if let Err(e) = print_shell_result(&result, jq_filter, color) {
+                                eprintln!("Error: {}", e);
+                            }
+                            tools_cache = Some(result.clone());
+                            last = Some(last_value(result));
+                        }
                         Err(e) => eprintln!("Error: {}", e),
                     }
                     continue;
                 }
 
+                // Argument completion: complete prompt:<name> <arg>=<partial>
+                if let Some(rest) = input.strip_prefix("complete ") {
+                    match rest.trim().split_once(' ') {
+                        Some((reference, argument)) => {
+                            match complete_via_daemon(&server_name, reference, argument.trim()) {
+                                Ok(values) if values.is_empty() => println!("(no completions)"),
+                                Ok(values) => {
+                                    for value in values {
+                                        println!("  {}", value);
+                                    }
+                                }
+                                Err(e) => eprintln!("Error: {}", e),
+                            }
+                        }
+                        None => eprintln!("Usage: complete <prompt:name|resource:uri> <arg>=<partial>"),
+                    }
+                    continue;
+                }
+
+                // Resources and prompts
+                let request = match input.split_once(' ').unwrap_or((input, "")) {
+                    ("list-resources", "") => Some(("resources/list", Ok(json!({})))),
+                    ("read", uri) if !uri.trim().is_empty() => {
+                        Some(("resources/read", Ok(json!({"uri": uri.trim()}))))
+                    }
+                    ("list-prompts", "") => Some(("prompts/list", Ok(json!({})))),
+                    ("prompt", rest) if !rest.trim().is_empty() => {
+                        let (name, args) = rest.trim().split_once(' ').unwrap_or((rest.trim(), "{}"));
+                        Some((
+                            "prompts/get",
+                            serde_json::from_str::<Value>(args)
+                                .map(|args| json!({"name": name, "arguments": args})),
+                        ))
+                    }
+                    _ => None,
+                };
+                if let Some((method, params)) = request {
+                    match params {
+                        Ok(params) => match request_via_daemon(&server_name, method, params) {
+                            Ok(result) => {
+                                if let Err(e) = print_shell_result(&result, jq_filter, color) {
+                                    eprintln!("Error: {}", e);
+                                }
+                                last = Some(last_value(result));
+                            }
+                            Err(e) => eprintln!("Error: {}", e),
+                        },
+                        Err(e) => eprintln!("Invalid JSON args: {}", e),
+                    }
+                    continue;
+                }
+
                 // Parse "call tool_name args" format
                 if let Some(rest) = input.strip_prefix("call ") {
-                    let parts: Vec<&str> = rest.splitn(2, ' ').collect();
-                    if !parts.is_empty() {
-                        let tool = parts[0];
-                        let args = parts.get(1).unwrap_or(&"{}");
-
-                        match serde_json::from_str(args) {
+                    let (tool, args) = split_call_line(rest);
+                    if !tool.is_empty() {
+                        let args = match substitute_last(args, last.as_ref()) {
+                            Ok(args) => args,
+                            Err(e) => {
+                                eprintln!("Error: {}", e);
+                                continue;
+                            }
+                        };
+
+                        match serde_json::from_str(&args) {
                             Ok(args_json) => match call_via_daemon(&server_name, tool, args_json) {
                                 Ok(result) => {
-                                    println!("{}", serde_json::to_string_pretty(&result)?)
+                                    if let Err(e) = print_shell_result(&result, jq_filter, color) {
+                                        eprintln!("Error: {}", e);
+                                    }
+                                    last = Some(last_value(result));
                                 }
                                 Err(e) => eprintln!("Error: {}", e),
                             },
@@ -1120,7 +7814,10 @@ fn main() -> Result<()> {
                         eprintln!("Usage: call <tool_name> [json_args]");
                     }
                 } else {
-                    eprintln!("Usage: call <tool_name> [json_args] | list-tools | exit");
+                    eprintln!(
+                        "Usage: call <tool_name> [json_args] | help | list-tools | list-resources | read <uri> | \
+                        list-prompts | prompt <name> [json_args] | complete <ref> <arg>=<partial> | use <server> | exit"
+                    );
                 }
             }
 
@@ -1128,26 +7825,36 @@ fn main() -> Result<()> {
             Ok(())
         }
 
-        Commands::StartDaemon => {
+        Commands::StartDaemon { replace_running } => {
             let server_name = cli.server.ok_or_else(|| {
                 anyhow!("--server required")
             })?;
 
             let config = load_server_config(cli.config.clone())?;
-            let profile = config
-                .servers
-                .get(&server_name)
-                .ok_or_else(|| anyhow!("Server '{}' not found in config", server_name))?;
-
-            let extra_args = if let Some(args_str) = &cli.server_args {
-                Some(serde_json::from_str::<Vec<String>>(args_str)
-                    .context("Invalid JSON in --server-args")?)
-            } else {
-                None
-            };
+            let (server_name, profile) = config.resolve(&server_name)?;
+
+            let extra_args = parse_server_args(cli.server_args.as_deref(), cli.server_args_append.as_deref(), profile)?;
+
+            let env_overrides = parse_env_overrides(&cli.env)?;
+
+            if cli.dump_env {
+                let mut profile = profile.clone();
+                profile.env.extend(env_overrides);
+                print_env_dump(&profile, cli.show_secrets);
+                return Ok(());
+            }
 
             let daemon_mgr = DaemonManager::new(&server_name);
-            daemon_mgr.start(profile, extra_args)?;
+            if replace_running && daemon_mgr.is_running()? {
+                eprintln!("Replacing running daemon for '{}'", server_name);
+                daemon_mgr.stop()?;
+            }
+            daemon_mgr.start(profile, &DaemonLaunch {
+                config: config.selection.clone(),
+                server_args: extra_args,
+                env: env_overrides,
+                options: client_options,
+            })?;
             Ok(())
         }
 
@@ -1155,20 +7862,211 @@ fn main() -> Result<()> {
             let server_name = cli.server.ok_or_else(|| {
                 anyhow!("--server required")
             })?;
+            let server_name = resolve_server_name(cli.config.clone(), server_name);
+
+            let daemon_mgr = DaemonManager::new(&server_name);
+            daemon_mgr.stop()
+        }
+
+        Commands::RestartDaemon => {
+            let server_name = cli.server.ok_or_else(|| {
+                anyhow!("--server required")
+            })?;
+
+            let mut config = load_server_config(cli.config.clone())?;
+            let (server_name, _) = config.resolve(&server_name)?;
 
+            // Start it the way it was started, unless this command says otherwise
             let daemon_mgr = DaemonManager::new(&server_name);
+            let recorded = daemon_mgr.read_state().and_then(|state| state.launch);
+            if let Some(recorded) = recorded.as_ref().filter(|r| cli.config.is_none() && r.config != config.selection) {
+                config = load_selected_config(&recorded.config)?;
+            }
+            let profile = config.servers.get(&server_name)
+                .ok_or_else(|| Failure::ServerNotFound.wrap(anyhow!("Server '{}' not found", server_name)))?;
+            let server_args = parse_server_args(cli.server_args.as_deref(), cli.server_args_append.as_deref(), profile)?;
+            let mut launch = recorded
+                .unwrap_or_else(|| DaemonLaunch { config: config.selection.clone(), ..Default::default() })
+                .restarted(server_args, parse_env_overrides(&cli.env)?, &client_options);
+            if cli.config.is_some() {
+                launch.config = config.selection.clone();
+            }
+            if let Some(args) = &launch.server_args {
+                eprintln!("Server args: {}", serde_json::to_string(args)?);
+            }
+
             daemon_mgr.stop()?;
+            daemon_mgr.start(profile, &launch)?;
             Ok(())
         }
 
+        Commands::ResetDaemon => {
+            let server_name = cli.server.ok_or_else(|| {
+                anyhow!("--server required")
+            })?;
+            let server_name = resolve_server_name(cli.config.clone(), server_name);
+
+            let runtime = load_server_config(cli.config.clone())
+                .ok()
+                .and_then(|config| config.servers.get(&server_name)?.container.clone())
+                .map(|spec| spec.runtime);
+            DaemonManager::new(&server_name).reset(runtime.as_deref())
+        }
+
+        Commands::Prune { all, dry_run } => {
+            let config = load_server_config(cli.config.clone())?;
+            prune(&config, all, dry_run)
+        }
+
         Commands::DaemonStatus => {
             let server_name = cli.server.ok_or_else(|| {
                 anyhow!("--server required")
             })?;
+            let server_name = resolve_server_name(cli.config.clone(), server_name);
 
             let daemon_mgr = DaemonManager::new(&server_name);
             daemon_mgr.status()?;
             Ok(())
         }
+
+        Commands::Attach => {
+            let config = load_server_config(cli.config.clone())?;
+            let server_name = match cli.server {
+                Some(name) => name,
+                None => pick_server(&config)?,
+            };
+            let (server_name, _profile) = config.resolve(&server_name)?;
+
+            let daemon_mgr = DaemonManager::new(&server_name);
+            if !daemon_mgr.is_running().unwrap_or(false) {
+                return Err(daemon_not_running_error(&server_name));
+            }
+            attach(&daemon_mgr, color)
+        }
+
+        Commands::Logs { lines, since, file } => {
+            let path = match file {
+                Some(path) => path,
+                None => {
+                    let config = load_server_config(cli.config.clone())?;
+                    let server_name = match cli.server {
+                        Some(name) => name,
+                        None => pick_server(&config)?,
+                    };
+                    let (server_name, _profile) = config.resolve(&server_name)?;
+                    let path = DaemonManager::new(&server_name).log_file();
+                    if !path.exists() {
+                        return Err(anyhow!(
+                            "No daemon log for '{}' at {} (start a daemon from this project first)",
+                            server_name,
+                            path.display()
+                        ));
+                    }
+                    path
+                }
+            };
+            print_log_tail(&path, lines, since)
+        }
+
+        Commands::Version => {
+            println!("mcp-valve {}", env!("CARGO_PKG_VERSION"));
+
+            let Some(server_name) = cli.server else {
+                return Ok(());
+            };
+
+            let config = load_server_config(cli.config.clone())?;
+            let (server_name, profile) = config.resolve(&server_name)?;
+            let mut profile = profile.clone();
+            profile.env.extend(parse_env_overrides(&cli.env)?);
+
+            let extra_args = parse_server_args(cli.server_args.as_deref(), cli.server_args_append.as_deref(), &profile)?;
+
+            run_hook("pre_start", &profile.pre_start, &profile, &server_name)?;
+            let mcp = McpClient::start(&profile, extra_args, &server_name, &client_options)?;
+            let info = mcp.server_info();
+            let name = info
+                .and_then(|i| i.get("name"))
+                .and_then(|n| n.as_str())
+                .unwrap_or("unknown");
+            let version = info
+                .and_then(|i| i.get("version"))
+                .and_then(|v| v.as_str())
+                .unwrap_or("unknown");
+            println!("{}: {} {} (protocol {})", server_name, name, version, mcp.protocol_version);
+            Ok(())
+        }
+
+        Commands::Check { ping } => {
+            let config = load_server_config(cli.config.clone())?;
+            let server_name = match cli.server {
+                Some(name) => name,
+                None => pick_server(&config)?,
+            };
+            let (server_name, profile) = config.resolve(&server_name)?;
+            let mut profile = profile.clone();
+            profile.env.extend(parse_env_overrides(&cli.env)?);
+
+            let extra_args = parse_server_args(cli.server_args.as_deref(), cli.server_args_append.as_deref(), &profile)?;
+
+            run_hook("pre_start", &profile.pre_start, &profile, &server_name)?;
+            let started = Instant::now();
+            let mut mcp = McpClient::start(&profile, extra_args, &server_name, &client_options)?;
+            let mut report = json!({
+                "server": server_name,
+                "serverInfo": mcp.init_result["serverInfo"],
+                "protocolVersion": mcp.protocol_version,
+                "capabilities": mcp.init_result["capabilities"],
+                "initialize_ms": started.elapsed().as_millis() as u64,
+            });
+            if ping {
+                let started = Instant::now();
+                mcp.request("ping", json!({})).context("Server did not answer ping")?;
+                report["ping_ms"] = json!(started.elapsed().as_millis() as u64);
+            }
+            print_result(&report, &output)
+        }
+
+        Commands::Subscribe { uri, read } => {
+            let config = load_server_config(cli.config.clone())?;
+            let server_name = match cli.server {
+                Some(name) => name,
+                None => pick_server(&config)?,
+            };
+            let (server_name, profile) = config.resolve(&server_name)?;
+            let mut profile = profile.clone();
+            profile.env.extend(parse_env_overrides(&cli.env)?);
+
+            let extra_args = parse_server_args(cli.server_args.as_deref(), cli.server_args_append.as_deref(), &profile)?;
+
+            run_hook("pre_start", &profile.pre_start, &profile, &server_name)?;
+            let mut mcp = McpClient::start(&profile, extra_args, &server_name, &client_options)?;
+            if mcp.init_result["capabilities"]["resources"]["subscribe"] != true {
+                return Err(anyhow!("Server '{}' does not support resources/subscribe", server_name));
+            }
+
+            mcp.subscribe(&uri)?;
+            stop_on_interrupt();
+            eprintln!("Subscribed to {} (Ctrl-C to stop)", uri);
+
+            while !INTERRUPTED.load(Ordering::SeqCst) {
+                let Some(update) = mcp.next_resource_update(Duration::from_millis(200))? else {
+                    continue;
+                };
+                let updated = update["uri"].as_str().unwrap_or(&uri);
+                println!("[{}] updated {}", timestamp_rfc3339(), updated);
+                if read {
+                    match mcp.read_resource(updated) {
+                        Ok(result) => println!("{}", render_json(&result, color)?),
+                        Err(e) => eprintln!("Error: {}", e),
+                    }
+                }
+                std::io::stdout().flush()?;
+            }
+
+            eprintln!("Unsubscribing from {}", uri);
+            mcp.unsubscribe(&uri)?;
+            Ok(())
+        }
     }
 }
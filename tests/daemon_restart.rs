@@ -0,0 +1,89 @@
+//! Starts real daemons through the built binary, on a shell stub server
+#![cfg(target_os = "linux")]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Answers every request with an initialize result, which is all a daemon needs to start
+const STUB_SERVER: &str = r#"while IFS= read -r line; do
+  id=$(printf '%s' "$line" | sed -n 's/.*"id":\([0-9][0-9]*\).*/\1/p')
+  [ -n "$id" ] && printf '{"jsonrpc":"2.0","id":%s,"result":{"protocolVersion":"2025-06-18","capabilities":{},"serverInfo":{"name":"stub","version":"1"}}}\n' "$id"
+done
+"#;
+
+struct Project {
+    dir: PathBuf,
+}
+
+impl Project {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("mcp-valve-it-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("stub.sh"), STUB_SERVER).unwrap();
+        let config = serde_json::json!({
+            "stub": {
+                "command": ["sh", dir.join("stub.sh")],
+                "supports_daemon": true
+            }
+        });
+        fs::write(dir.join("servers.json"), config.to_string()).unwrap();
+        Self { dir }
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        let output = Command::new(env!("CARGO_BIN_EXE_mcp-valve"))
+            .args(["--config", "servers.json", "--server", "stub"])
+            .args(args)
+            .current_dir(&self.dir)
+            .env_remove("MCP_VALVE_CONFIG")
+            .env_remove("MCP_VALVE_CONFIG_PATH")
+            .env_remove("MCP_VALVE_PROFILE_DIR")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+        output
+    }
+
+    /// Command line of the MCP server the daemon currently runs
+    fn server_cmdline(&self) -> Vec<String> {
+        let state: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(self.profile_dir().join("daemon.json")).unwrap()).unwrap();
+        let pid = state["server_pid"].as_u64().expect("daemon.json has no server_pid");
+        cmdline(pid)
+    }
+
+    fn profile_dir(&self) -> PathBuf {
+        self.dir.join(".mcp-profile").join("stub")
+    }
+}
+
+impl Drop for Project {
+    fn drop(&mut self) {
+        let _ = Command::new(env!("CARGO_BIN_EXE_mcp-valve"))
+            .args(["--config", "servers.json", "--server", "stub", "stop-daemon"])
+            .current_dir(&self.dir)
+            .output();
+        fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+fn cmdline(pid: u64) -> Vec<String> {
+    let raw = fs::read(Path::new("/proc").join(pid.to_string()).join("cmdline")).unwrap();
+    raw.split(|&b| b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect()
+}
+
+#[test]
+fn restart_daemon_keeps_the_server_args() {
+    let project = Project::new("restart");
+    project.run(&["--server-args", r#"["--port","9000"]"#, "start-daemon"]);
+    let before = project.server_cmdline();
+    assert_eq!(before[before.len() - 2..], ["--port", "9000"]);
+
+    project.run(&["restart-daemon"]);
+    let after = project.server_cmdline();
+    assert_eq!(after, before);
+}